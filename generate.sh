@@ -68,7 +68,7 @@ docker run --rm -v "${PWD}:/local" -u $(id -u) openapitools/openapi-generator-cl
   -i "/local/$file" \
   -g rust \
   -o /local/openapi \
-  --additional-properties=packageVersion=0.1.0
+  --additional-properties=packageVersion=0.1.0,supportMiddleware=true
 
 # Apply codemod to codegen
 process_directory "openapi"
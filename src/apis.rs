@@ -0,0 +1,292 @@
+//! Management API endpoints that the published OpenAPI spec doesn't describe yet.
+//!
+//! These mirror the generated functions in `openapi::apis`, returning the same `Error` type so
+//! they convert into a crate `Error` the same way. They live outside `openapi` so regenerating
+//! the client with `generate.sh` doesn't remove them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::openapi::apis::configuration::Configuration;
+use crate::openapi::apis::transactions_api::CreateAuthenticateTransactionError;
+use crate::openapi::apis::{urlencode, Error, ResponseContent};
+use crate::openapi::models;
+
+/// The body of a create user request.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CreateUserArgs {
+    /// Email of the new user. Either this, `phone` or `external_id` is required.
+    #[serde(rename = "email", skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// The external ID of the user. Only set if the user was created in a Flex app.
+    #[serde(rename = "external_id", skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    /// Phone number of the new user. Either this, `email` or `external_id` is required.
+    #[serde(rename = "phone", skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(rename = "user_metadata", skip_serializing_if = "Option::is_none")]
+    pub user_metadata: Option<serde_json::Value>,
+}
+
+/// Typed errors of [`create_user`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CreateUserError {
+    Status400(models::Model400Error),
+    Status401(models::Model401Error),
+    Status404(models::Model404Error),
+    Status500(models::Model500Error),
+    UnknownValue(serde_json::Value),
+}
+
+/// Create a user.
+pub async fn create_user(
+    configuration: &Configuration,
+    create_user_args: CreateUserArgs,
+) -> Result<models::UserResponse, Error<CreateUserError>> {
+    let content = send(
+        configuration,
+        reqwest::Method::POST,
+        "/users".to_string(),
+        Some(&create_user_args),
+    )
+    .await?;
+    serde_json::from_str(&content).map_err(Error::from)
+}
+
+/// Typed errors of [`delete_user`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DeleteUserError {
+    Status401(models::Model401Error),
+    Status404(models::Model404Error),
+    Status500(models::Model500Error),
+    UnknownValue(serde_json::Value),
+}
+
+/// Delete a user.
+pub async fn delete_user(
+    configuration: &Configuration,
+    user_id: &str,
+) -> Result<(), Error<DeleteUserError>> {
+    send(
+        configuration,
+        reqwest::Method::DELETE,
+        format!("/users/{}", urlencode(user_id)),
+        None::<&()>,
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Typed errors of [`activate_user`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ActivateUserError {
+    Status401(models::Model401Error),
+    Status404(models::Model404Error),
+    Status500(models::Model500Error),
+    UnknownValue(serde_json::Value),
+}
+
+/// Activate a user. They will now be able to login.
+pub async fn activate_user(
+    configuration: &Configuration,
+    user_id: &str,
+) -> Result<models::UserResponse, Error<ActivateUserError>> {
+    let content = send(
+        configuration,
+        reqwest::Method::PATCH,
+        format!("/users/{}/activate", urlencode(user_id)),
+        None::<&()>,
+    )
+    .await?;
+    serde_json::from_str(&content).map_err(Error::from)
+}
+
+/// Typed errors of [`deactivate_user`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DeactivateUserError {
+    Status401(models::Model401Error),
+    Status404(models::Model404Error),
+    Status500(models::Model500Error),
+    UnknownValue(serde_json::Value),
+}
+
+/// Deactivate a user. Their status will be set to inactive, and they will not be able to login.
+pub async fn deactivate_user(
+    configuration: &Configuration,
+    user_id: &str,
+) -> Result<models::UserResponse, Error<DeactivateUserError>> {
+    let content = send(
+        configuration,
+        reqwest::Method::PATCH,
+        format!("/users/{}/deactivate", urlencode(user_id)),
+        None::<&()>,
+    )
+    .await?;
+    serde_json::from_str(&content).map_err(Error::from)
+}
+
+/// Create a transaction to start a WebAuthn authentication ceremony without naming the user,
+/// who picks a discoverable credential instead.
+///
+/// The spec marks `external_id` as required on this endpoint, so omitting it relies on server
+/// behavior that Passage doesn't document. The body is the generated request model with
+/// `external_id` removed, so a regenerated model with new fields fails to build here.
+pub async fn create_discoverable_authenticate_transaction(
+    configuration: &Configuration,
+) -> Result<models::CreateTransactionResponse, Error<CreateAuthenticateTransactionError>> {
+    let content = send(
+        configuration,
+        reqwest::Method::POST,
+        "/transactions/authenticate".to_string(),
+        Some(&discoverable_authenticate_request()),
+    )
+    .await?;
+    serde_json::from_str(&content).map_err(Error::from)
+}
+
+/// The body of a create authenticate transaction request that doesn't name the user.
+fn discoverable_authenticate_request() -> serde_json::Value {
+    let request = models::CreateTransactionAuthenticateRequest {
+        external_id: String::new(),
+    };
+    let mut body = serde_json::to_value(request).unwrap_or_default();
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove("external_id");
+    }
+    body
+}
+
+/// Sends a request with the configuration's user agent and bearer token, returning the
+/// response body of a successful response.
+async fn send<E: serde::de::DeserializeOwned>(
+    configuration: &Configuration,
+    method: reqwest::Method,
+    path: String,
+    body: Option<&impl Serialize>,
+) -> Result<String, Error<E>> {
+    let client = &configuration.client;
+    let uri = format!("{}{}", configuration.base_path, path);
+    let mut request = client.request(method, uri.as_str());
+
+    if let Some(user_agent) = &configuration.user_agent {
+        request = request.header(reqwest::header::USER_AGENT, user_agent.clone());
+    }
+    if let Some(token) = &configuration.bearer_access_token {
+        request = request.bearer_auth(token.to_owned());
+    }
+    if let Some(body) = body {
+        request = request.json(body);
+    }
+
+    let response = client.execute(request.build()?).await?;
+    let status = response.status();
+    let content = response.text().await?;
+
+    if !status.is_client_error() && !status.is_server_error() {
+        Ok(content)
+    } else {
+        let entity: Option<E> = serde_json::from_str(&content).ok();
+        Err(Error::ResponseError(ResponseContent {
+            status,
+            content,
+            entity,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{user_response, TestServer};
+
+    #[tokio::test]
+    async fn discoverable_transactions_omit_only_the_external_id() {
+        let server =
+            TestServer::start(|_| async { (200, r#"{"transaction_id":"txn-auth"}"#.to_string()) })
+                .await;
+
+        let response = create_discoverable_authenticate_transaction(&server.configuration())
+            .await
+            .unwrap();
+
+        assert_eq!(response.transaction_id, "txn-auth");
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/transactions/authenticate");
+        assert_eq!(request.body, "{}");
+    }
+
+    #[tokio::test]
+    async fn create_user_posts_only_set_fields() {
+        let server =
+            TestServer::start(|_| async { (201, user_response("user-1", serde_json::json!([]))) })
+                .await;
+        let mut configuration = server.configuration();
+        configuration.bearer_access_token = Some("api-key".to_string());
+
+        let response = create_user(
+            &configuration,
+            CreateUserArgs {
+                email: None,
+                external_id: Some("user-1".to_string()),
+                phone: None,
+                user_metadata: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.user.external_id, "user-1");
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/users");
+        assert_eq!(request.body, r#"{"external_id":"user-1"}"#);
+        assert_eq!(request.header("authorization"), Some("Bearer api-key"));
+    }
+
+    #[tokio::test]
+    async fn deactivate_user_patches_the_user() {
+        let server =
+            TestServer::start(|_| async { (200, user_response("user-1", serde_json::json!([]))) })
+                .await;
+
+        let response = deactivate_user(&server.configuration(), "id/1")
+            .await
+            .unwrap();
+        assert_eq!(response.user.external_id, "user-1");
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "PATCH");
+        assert_eq!(request.path, "/users/id%2F1/deactivate");
+    }
+
+    #[tokio::test]
+    async fn error_responses_carry_their_entity() {
+        let server = TestServer::start(|_| async {
+            (
+                400,
+                r#"{"code":"invalid_request","error":"bad external_id"}"#.to_string(),
+            )
+        })
+        .await;
+
+        let error = create_user(
+            &server.configuration(),
+            CreateUserArgs {
+                email: None,
+                external_id: Some(" ".to_string()),
+                phone: None,
+                user_metadata: None,
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            crate::Error::from(error),
+            crate::Error::InvalidRequest(message) if message == "bad external_id"
+        ));
+    }
+}
@@ -1,15 +1,140 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::apis;
+use crate::cache::CacheBackend;
+use crate::call_options::{self, CallOptions, Replay, Retry};
+use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+use crate::client_limiter::{self, ClientLimiter, Priority};
+use crate::digest::sha256_hex;
+use crate::enumeration::{AuditOutcome, EnumerationProtection};
+use crate::models::{PassageUser, Transaction, TransactionKind, VerifiedNonce, VerifyOptions};
+use crate::nonce_store::NonceStore;
 use crate::openapi::apis::configuration::Configuration;
 use crate::openapi::apis::{authenticate_api, transactions_api};
+use crate::openapi::models::{UserEventAction, UserEventStatus, UserInfo, WebAuthnDevices};
+use crate::rate_limit::RateLimiter;
+use crate::session_store::{Session, SessionStore};
+use crate::step_up::{StepUp, StepUpChallenge, StepUpProof};
+use crate::telemetry::{self, Operation};
+use crate::throttle::Throttle;
+use crate::transaction_tracker::{self, PendingTransaction, TransactionStore};
+use crate::user::User;
 use crate::Error;
 
+/// Decides whether `Auth::begin` may fall back to registration when a user cannot authenticate.
+#[derive(Clone, Default)]
+pub enum RegistrationPolicy {
+    /// Register any user that doesn't exist or has no passkeys.
+    #[default]
+    Always,
+    /// Never register; `begin` returns the authentication error instead.
+    Never,
+    /// Register only when the check resolves to `true` for the external ID, e.g. when the user
+    /// exists in your own database.
+    When(Arc<dyn Fn(String) -> BoxFuture<'static, Result<bool, Error>> + Send + Sync>),
+}
+
+impl RegistrationPolicy {
+    /// Creates a `RegistrationPolicy::When` from an async check.
+    pub fn when<F, Fut>(check: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<bool, Error>> + Send + 'static,
+    {
+        Self::When(Arc::new(move |external_id| Box::pin(check(external_id))))
+    }
+
+    async fn allows(&self, external_id: &str) -> Result<bool, Error> {
+        match self {
+            Self::Always => Ok(true),
+            Self::Never => Ok(false),
+            Self::When(check) => check(external_id.to_string()).await,
+        }
+    }
+}
+
+impl std::fmt::Debug for RegistrationPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Always => write!(f, "Always"),
+            Self::Never => write!(f, "Never"),
+            Self::When(_) => write!(f, "When(..)"),
+        }
+    }
+}
+
+/// The ceremony the frontend should run, as decided by `Auth::begin`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "ceremony", content = "transaction", rename_all = "snake_case")]
+pub enum Ceremony {
+    Register(Transaction),
+    Authenticate(Transaction),
+}
+
+impl Ceremony {
+    /// The transaction to hand to the frontend.
+    pub fn transaction(&self) -> &Transaction {
+        match self {
+            Self::Register(transaction) | Self::Authenticate(transaction) => transaction,
+        }
+    }
+}
+
 pub struct Auth {
+    pub(crate) app_id: String,
     pub(crate) configuration: Configuration,
+    pub(crate) registration_policy: RegistrationPolicy,
+    pub(crate) nonce_store: Option<Arc<dyn NonceStore>>,
+    pub(crate) transaction_store: Option<(Arc<dyn TransactionStore>, Duration)>,
+    pub(crate) session_store: Option<(Arc<dyn SessionStore>, Duration)>,
+    pub(crate) step_up: Option<StepUp>,
+    pub(crate) throttle: Option<Throttle>,
+    pub(crate) enumeration_protection: Option<EnumerationProtection>,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    pub(crate) client_limiter: Option<Arc<ClientLimiter>>,
+    pub(crate) circuit_breaker: Option<Arc<CircuitBreaker>>,
+    pub(crate) cache: Option<(Arc<dyn CacheBackend>, Duration)>,
+    pub(crate) retry: Retry,
 }
 
 impl Auth {
     /// Creates a new instance of the `Auth` struct.
     pub fn new(configuration: Configuration) -> Self {
-        Self { configuration }
+        Self {
+            app_id: String::new(),
+            configuration,
+            registration_policy: RegistrationPolicy::default(),
+            nonce_store: None,
+            transaction_store: None,
+            session_store: None,
+            step_up: None,
+            throttle: None,
+            enumeration_protection: None,
+            rate_limiter: None,
+            client_limiter: None,
+            circuit_breaker: None,
+            cache: None,
+            retry: Retry::none(),
+        }
+    }
+
+    /// A `User` for the lookups made while authenticating, sharing this client's limiters,
+    /// breaker, cache and retry policy.
+    fn lookups(&self) -> User {
+        let mut user = User::new(self.configuration.clone());
+        user.app_id = self.app_id.clone();
+        user.client_limiter = self.client_limiter.clone();
+        user.priority = Priority::Interactive;
+        user.circuit_breaker = self.circuit_breaker.clone();
+        user.cache = self.cache.clone();
+        user.retry = self.retry;
+        user
     }
 
     /// Creates a transaction to start a user's registration process.
@@ -21,7 +146,7 @@ impl Auth {
     ///
     /// # Returns
     ///
-    /// A `Result` containing the transaction ID as a string or an `Error`.
+    /// A `Result` containing the `Transaction` or an `Error`.
     ///
     /// # Examples
     ///
@@ -41,12 +166,52 @@ impl Auth {
     ///     )
     ///     .await
     ///     .unwrap();
+    ///
+    /// // send to the frontend to start the registration ceremony
+    /// let payload = serde_json::to_string(&transaction.to_frontend()).unwrap();
     /// ```
     pub async fn create_register_transaction(
         &self,
         external_id: String,
         passkey_display_name: String,
-    ) -> Result<String, Error> {
+    ) -> Result<Transaction, Error> {
+        self.create_register_transaction_as(external_id, passkey_display_name, None)
+            .await
+    }
+
+    /// Like `Auth::create_register_transaction`, with options for this call.
+    pub async fn create_register_transaction_with_options(
+        &self,
+        external_id: String,
+        passkey_display_name: String,
+        call_options: CallOptions,
+    ) -> Result<Transaction, Error> {
+        call_options
+            .run(self.create_register_transaction(external_id, passkey_display_name))
+            .await
+    }
+
+    async fn create_register_transaction_as(
+        &self,
+        external_id: String,
+        passkey_display_name: String,
+        caller: Option<&str>,
+    ) -> Result<Transaction, Error> {
+        Operation::start("auth.create_register_transaction", Some(&external_id))
+            .run(self.create_register_transaction_untraced(
+                external_id,
+                passkey_display_name,
+                caller,
+            ))
+            .await
+    }
+
+    async fn create_register_transaction_untraced(
+        &self,
+        external_id: String,
+        passkey_display_name: String,
+        caller: Option<&str>,
+    ) -> Result<Transaction, Error> {
         if external_id.is_empty() {
             return Err(Error::InvalidArgument(
                 "external_id is required".to_string(),
@@ -59,16 +224,33 @@ impl Auth {
             ));
         }
 
-        transactions_api::create_register_transaction(
-            &self.configuration,
-            crate::openapi::models::CreateTransactionRegisterRequest {
-                external_id,
-                passkey_display_name,
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire(TransactionKind::Register, Some(&external_id), caller)?;
+        }
+
+        let request = self.send(
+            "create_register_transaction",
+            Replay::WithIdempotencyKey,
+            || {
+                transactions_api::create_register_transaction(
+                    &self.configuration,
+                    crate::openapi::models::CreateTransactionRegisterRequest {
+                        external_id: external_id.clone(),
+                        passkey_display_name: passkey_display_name.clone(),
+                    },
+                )
             },
-        )
-        .await
-        .map(|response| response.transaction_id)
-        .map_err(Into::into)
+        );
+        self.throttled(Some(&external_id), caller, request)
+            .await
+            .map(|response| {
+                Transaction::new(
+                    response.transaction_id,
+                    TransactionKind::Register,
+                    self.app_id.clone(),
+                    Some(external_id.clone()),
+                )
+            })
     }
 
     /// Creates a transaction to start a user's authentication process.
@@ -79,7 +261,10 @@ impl Auth {
     ///
     /// # Returns
     ///
-    /// A `Result` containing the transaction ID as a string or an `Error`.
+    /// A `Result` containing the `Transaction` or an `Error`. With enumeration protection
+    /// enabled, every user gets a usernameless transaction, and unknown users and users
+    /// without passkeys get one that can't authenticate them instead of `Error::UserNotFound`
+    /// or `Error::UserHasNoPasskeys`.
     ///
     /// # Examples
     ///
@@ -98,24 +283,290 @@ impl Auth {
     ///     )
     ///     .await
     ///     .unwrap();
+    ///
+    /// // send to the frontend to start the authentication ceremony
+    /// let payload = serde_json::to_string(&transaction.to_frontend()).unwrap();
     /// ```
     pub async fn create_authenticate_transaction(
         &self,
         external_id: String,
-    ) -> Result<String, Error> {
+    ) -> Result<Transaction, Error> {
+        self.create_authenticate_transaction_as(external_id, None)
+            .await
+    }
+
+    /// Like `Auth::create_authenticate_transaction`, with options for this call.
+    pub async fn create_authenticate_transaction_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<Transaction, Error> {
+        call_options
+            .run(self.create_authenticate_transaction(external_id))
+            .await
+    }
+
+    /// Creates an authenticate transaction, or a usernameless one for every user when
+    /// enumeration protection is enabled.
+    async fn create_authenticate_transaction_as(
+        &self,
+        external_id: String,
+        caller: Option<&str>,
+    ) -> Result<Transaction, Error> {
+        Operation::start("auth.create_authenticate_transaction", Some(&external_id))
+            .run(self.create_authenticate_transaction_untraced(external_id, caller))
+            .await
+    }
+
+    async fn create_authenticate_transaction_untraced(
+        &self,
+        external_id: String,
+        caller: Option<&str>,
+    ) -> Result<Transaction, Error> {
+        let Some(protection) = &self.enumeration_protection else {
+            return self
+                .request_authenticate_transaction(external_id, caller)
+                .await;
+        };
+
+        let started_at = tokio::time::Instant::now();
+        let result = self
+            .protected_authenticate_transaction(protection, external_id, caller)
+            .await;
+        tokio::time::sleep_until(started_at + protection.min_response_time).await;
+        result
+    }
+
+    /// Looks the user up to learn the real outcome, then returns a usernameless transaction
+    /// whatever it is, since a named one lists the user's credentials and so would tell a real
+    /// user from a decoy.
+    async fn protected_authenticate_transaction(
+        &self,
+        protection: &EnumerationProtection,
+        external_id: String,
+        caller: Option<&str>,
+    ) -> Result<Transaction, Error> {
+        if external_id.is_empty() {
+            return Err(Error::InvalidArgument(
+                "external_id is required".to_string(),
+            ));
+        }
+
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire(TransactionKind::Authenticate, Some(&external_id), caller)?;
+        }
+
+        let lookups = self.lookups();
+        // Boxed, since the lookup's future would otherwise be copied into every caller's.
+        let lookup = Box::pin(lookups.get(external_id.clone()));
+        let outcome = match self.throttled(Some(&external_id), caller, lookup).await {
+            Ok(user) if user.webauthn_devices.is_empty() => AuditOutcome::UserHasNoPasskeys,
+            Ok(_) => AuditOutcome::Found,
+            Err(Error::UserNotFound) => AuditOutcome::UserNotFound,
+            Err(e) => return Err(e),
+        };
+
+        let mut transaction = self.create_discoverable_authenticate_transaction().await?;
+        transaction.external_id = Some(external_id.clone());
+        protection.report(&external_id, &transaction.id, outcome);
+        Ok(transaction)
+    }
+
+    async fn request_authenticate_transaction(
+        &self,
+        external_id: String,
+        caller: Option<&str>,
+    ) -> Result<Transaction, Error> {
         if external_id.is_empty() {
             return Err(Error::InvalidArgument(
                 "external_id is required".to_string(),
             ));
         }
 
-        transactions_api::create_authenticate_transaction(
-            &self.configuration,
-            crate::openapi::models::CreateTransactionAuthenticateRequest { external_id },
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire(TransactionKind::Authenticate, Some(&external_id), caller)?;
+        }
+
+        let request = self.send(
+            "create_authenticate_transaction",
+            Replay::WithIdempotencyKey,
+            || {
+                transactions_api::create_authenticate_transaction(
+                    &self.configuration,
+                    crate::openapi::models::CreateTransactionAuthenticateRequest {
+                        external_id: external_id.clone(),
+                    },
+                )
+            },
+        );
+        self.throttled(Some(&external_id), caller, request)
+            .await
+            .map(|response| {
+                Transaction::new(
+                    response.transaction_id,
+                    TransactionKind::Authenticate,
+                    self.app_id.clone(),
+                    Some(external_id.clone()),
+                )
+            })
+    }
+
+    /// Creates a transaction to start a usernameless authentication, where the user picks one
+    /// of their discoverable passkeys without entering an identifier first.
+    ///
+    /// The user is identified once the ceremony completes: `verify_nonce` returns the external
+    /// ID of whoever authenticated.
+    ///
+    /// Passage's API spec requires an external ID when creating an authenticate transaction.
+    /// This sends the request without one, relying on behavior Passage doesn't document, so it
+    /// may stop working if Passage starts enforcing the spec.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Transaction` or an `Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::PassageFlex;
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// );
+    ///
+    /// let transaction = passage_flex
+    ///     .auth
+    ///     .create_discoverable_authenticate_transaction()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// // once the frontend returns a nonce
+    /// let external_id = passage_flex.auth.verify_nonce(nonce).await.unwrap();
+    /// ```
+    pub async fn create_discoverable_authenticate_transaction(&self) -> Result<Transaction, Error> {
+        Operation::start("auth.create_discoverable_authenticate_transaction", None)
+            .run(self.create_discoverable_authenticate_transaction_untraced())
+            .await
+    }
+
+    /// Like `Auth::create_discoverable_authenticate_transaction`, with options for this call.
+    pub async fn create_discoverable_authenticate_transaction_with_options(
+        &self,
+        call_options: CallOptions,
+    ) -> Result<Transaction, Error> {
+        call_options
+            .run(self.create_discoverable_authenticate_transaction())
+            .await
+    }
+
+    async fn create_discoverable_authenticate_transaction_untraced(
+        &self,
+    ) -> Result<Transaction, Error> {
+        self.send(
+            "create_authenticate_transaction",
+            Replay::WithIdempotencyKey,
+            || apis::create_discoverable_authenticate_transaction(&self.configuration),
         )
         .await
-        .map(|response| response.transaction_id)
-        .map_err(Into::into)
+        .map(|response| {
+            Transaction::new(
+                response.transaction_id,
+                TransactionKind::Authenticate,
+                self.app_id.clone(),
+                None,
+            )
+        })
+    }
+
+    /// Starts an authentication for the user, falling back to registration when the user
+    /// doesn't exist or has no passkeys and the registration policy allows it.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - A unique, immutable string that represents the user.
+    /// * `passkey_display_name` - The label for the user's passkey, used if they register.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Ceremony` to run or an `Error`. When registration is not
+    /// allowed, the error is `Error::UserNotFound` or `Error::UserHasNoPasskeys`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::auth::{Ceremony, RegistrationPolicy};
+    /// use passage_flex::PassageFlex;
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// )
+    /// .with_registration_policy(RegistrationPolicy::when(|external_id| async move {
+    ///     Ok(my_db::user_exists(&external_id).await)
+    /// }));
+    ///
+    /// match passage_flex
+    ///     .auth
+    ///     .begin(
+    ///         "00000000-0000-0000-0000-000000000001".to_string(),
+    ///         "user@example.com".to_string(),
+    ///     )
+    ///     .await
+    /// {
+    ///     Ok(Ceremony::Register(transaction)) => { /* run passkey registration */ }
+    ///     Ok(Ceremony::Authenticate(transaction)) => { /* run passkey authentication */ }
+    ///     Err(err) => { /* user can't use passkeys */ }
+    /// }
+    /// ```
+    pub async fn begin(
+        &self,
+        external_id: String,
+        passkey_display_name: String,
+    ) -> Result<Ceremony, Error> {
+        Operation::start("auth.begin", Some(&external_id))
+            .run(self.begin_untraced(external_id, passkey_display_name))
+            .await
+    }
+
+    /// Like `Auth::begin`, with options for this call.
+    pub async fn begin_with_options(
+        &self,
+        external_id: String,
+        passkey_display_name: String,
+        call_options: CallOptions,
+    ) -> Result<Ceremony, Error> {
+        call_options
+            .run(self.begin(external_id, passkey_display_name))
+            .await
+    }
+
+    async fn begin_untraced(
+        &self,
+        external_id: String,
+        passkey_display_name: String,
+    ) -> Result<Ceremony, Error> {
+        if passkey_display_name.is_empty() {
+            return Err(Error::InvalidArgument(
+                "passkey_display_name is required".to_string(),
+            ));
+        }
+
+        match self
+            .request_authenticate_transaction(external_id.clone(), None)
+            .await
+        {
+            Ok(transaction) => Ok(Ceremony::Authenticate(transaction)),
+            Err(e @ (Error::UserNotFound | Error::UserHasNoPasskeys)) => {
+                if !self.registration_policy.allows(&external_id).await? {
+                    return Err(e);
+                }
+                self.create_register_transaction(external_id, passkey_display_name)
+                    .await
+                    .map(Ceremony::Register)
+            }
+            Err(e) => Err(e),
+        }
     }
 
     /// Verifies the nonce received from a WebAuthn registration or authentication ceremony.
@@ -126,7 +577,8 @@ impl Auth {
     ///
     /// # Returns
     ///
-    /// A `Result` containing the external ID as a string or an `Error`.
+    /// A `Result` containing the external ID as a string or an `Error`. If a nonce store is
+    /// configured, a nonce that was already accepted returns `Error::NonceReplayed`.
     ///
     /// # Examples
     ///
@@ -148,16 +600,1409 @@ impl Auth {
     /// }
     /// ```
     pub async fn verify_nonce(&self, nonce: String) -> Result<String, Error> {
+        self.verify_nonce_as(nonce, None, None).await
+    }
+
+    /// Like `Auth::verify_nonce`, with options for this call.
+    pub async fn verify_nonce_with_options(
+        &self,
+        nonce: String,
+        call_options: CallOptions,
+    ) -> Result<String, Error> {
+        call_options.run(self.verify_nonce(nonce)).await
+    }
+
+    /// Verifies a nonce, counting a failure against the expected user (if known) and caller.
+    async fn verify_nonce_as(
+        &self,
+        nonce: String,
+        expected_external_id: Option<&str>,
+        caller: Option<&str>,
+    ) -> Result<String, Error> {
+        Operation::start("auth.verify_nonce", expected_external_id)
+            .run(self.verify_nonce_untraced(nonce, expected_external_id, caller))
+            .await
+    }
+
+    async fn verify_nonce_untraced(
+        &self,
+        nonce: String,
+        expected_external_id: Option<&str>,
+        caller: Option<&str>,
+    ) -> Result<String, Error> {
         if nonce.is_empty() {
             return Err(Error::InvalidArgument("nonce is required".to_string()));
         }
 
-        authenticate_api::authenticate_verify_nonce(
-            &self.configuration,
-            crate::openapi::models::Nonce { nonce },
-        )
+        let external_id = self
+            .throttled(
+                expected_external_id,
+                caller,
+                self.verify_nonce_unthrottled(nonce),
+            )
+            .await?;
+        telemetry::record_external_id(&external_id);
+        if let Some(throttle) = &self.throttle {
+            throttle.record_success(&external_id);
+        }
+        Ok(external_id)
+    }
+
+    async fn verify_nonce_unthrottled(&self, nonce: String) -> Result<String, Error> {
+        let digest = match &self.nonce_store {
+            Some(store) => {
+                let digest = sha256_hex(&nonce);
+                if store.contains(&digest).await? {
+                    return Err(Error::NonceReplayed);
+                }
+                Some(digest)
+            }
+            None => None,
+        };
+
+        let external_id = self
+            .send("authenticate_verify_nonce", Replay::Never, || {
+                authenticate_api::authenticate_verify_nonce(
+                    &self.configuration,
+                    crate::openapi::models::Nonce {
+                        nonce: nonce.clone(),
+                    },
+                )
+            })
+            .await
+            .map(|response| response.external_id)?;
+
+        if let (Some(store), Some(digest)) = (&self.nonce_store, digest) {
+            if !store.insert(&digest).await? {
+                return Err(Error::NonceReplayed);
+            }
+        }
+
+        Ok(external_id)
+    }
+
+    /// Records a newly issued transaction against the session that requested it, so that its
+    /// nonce can later be verified with `verify_tracked_nonce`.
+    ///
+    /// # Arguments
+    ///
+    /// * `transaction` - The transaction returned by one of the `create_*_transaction` methods.
+    /// * `session_binding` - An opaque value identifying the requesting session, such as a session ID.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Transaction` with its expiry set, or an `Error`.
+    pub async fn track_transaction(
+        &self,
+        transaction: Transaction,
+        session_binding: &str,
+    ) -> Result<Transaction, Error> {
+        Operation::start("auth.track_transaction", transaction.external_id.as_deref())
+            .run(self.track_transaction_untraced(transaction, session_binding))
+            .await
+    }
+
+    /// Like `Auth::track_transaction`, with options for this call.
+    pub async fn track_transaction_with_options(
+        &self,
+        transaction: Transaction,
+        session_binding: &str,
+        call_options: CallOptions,
+    ) -> Result<Transaction, Error> {
+        call_options
+            .run(self.track_transaction(transaction, session_binding))
+            .await
+    }
+
+    async fn track_transaction_untraced(
+        &self,
+        transaction: Transaction,
+        session_binding: &str,
+    ) -> Result<Transaction, Error> {
+        if session_binding.is_empty() {
+            return Err(Error::InvalidArgument(
+                "session_binding is required".to_string(),
+            ));
+        }
+
+        let (store, ttl) = self.transaction_store()?;
+        let transaction = transaction_tracker::with_expiry(transaction, *ttl);
+        store
+            .put(PendingTransaction {
+                transaction: transaction.clone(),
+                session_digest: sha256_hex(session_binding),
+            })
+            .await?;
+        Ok(transaction)
+    }
+
+    /// Verifies a nonce for a transaction recorded with `track_transaction`.
+    ///
+    /// The nonce is only accepted if the transaction is still pending, was issued to the same
+    /// session, and was issued for the user the nonce verifies to. The nonce isn't checked
+    /// against the transaction itself, since Passage doesn't report which transaction it
+    /// completed. A transaction can be redeemed once.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonce` - The nonce string to be verified.
+    /// * `transaction_id` - The ID of the transaction the frontend completed.
+    /// * `session_binding` - The same value passed to `track_transaction`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the external ID as a string or an `Error`. Unknown transactions
+    /// return `Error::TransactionNotFound`, expired ones `Error::TransactionExpired`, and a
+    /// different session or user `Error::TransactionMismatch`.
+    pub async fn verify_tracked_nonce(
+        &self,
+        nonce: String,
+        transaction_id: &str,
+        session_binding: &str,
+    ) -> Result<String, Error> {
+        Operation::start("auth.verify_tracked_nonce", None)
+            .run(self.verify_tracked_nonce_untraced(nonce, transaction_id, session_binding))
+            .await
+    }
+
+    /// Like `Auth::verify_tracked_nonce`, with options for this call.
+    pub async fn verify_tracked_nonce_with_options(
+        &self,
+        nonce: String,
+        transaction_id: &str,
+        session_binding: &str,
+        call_options: CallOptions,
+    ) -> Result<String, Error> {
+        call_options
+            .run(self.verify_tracked_nonce(nonce, transaction_id, session_binding))
+            .await
+    }
+
+    async fn verify_tracked_nonce_untraced(
+        &self,
+        nonce: String,
+        transaction_id: &str,
+        session_binding: &str,
+    ) -> Result<String, Error> {
+        if transaction_id.is_empty() {
+            return Err(Error::InvalidArgument(
+                "transaction_id is required".to_string(),
+            ));
+        }
+
+        if session_binding.is_empty() {
+            return Err(Error::InvalidArgument(
+                "session_binding is required".to_string(),
+            ));
+        }
+
+        let (store, _) = self.transaction_store()?;
+        let pending = store
+            .get(transaction_id)
+            .await?
+            .ok_or(Error::TransactionNotFound)?;
+
+        if pending.transaction.is_expired() {
+            store.remove(transaction_id).await?;
+            return Err(Error::TransactionExpired);
+        }
+
+        if pending.session_digest != sha256_hex(session_binding) {
+            return Err(Error::TransactionMismatch);
+        }
+
+        let external_id = self
+            .verify_nonce_as(nonce, pending.transaction.external_id.as_deref(), None)
+            .await?;
+        telemetry::record_external_id(&external_id);
+
+        if !store.remove(transaction_id).await? {
+            return Err(Error::TransactionNotFound);
+        }
+
+        if !pending.transaction.matches(&external_id) {
+            return Err(Error::TransactionMismatch);
+        }
+
+        Ok(external_id)
+    }
+
+    /// Creates a handle that also counts failures against a caller key, such as the client's
+    /// IP address, when a throttle is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `caller_key` - An opaque value identifying the caller.
+    pub fn for_caller<'a>(&'a self, caller_key: &'a str) -> CallerAuth<'a> {
+        CallerAuth {
+            auth: self,
+            caller_key,
+        }
+    }
+
+    /// Sends a request to Passage through the circuit breaker, ahead of bulk management calls
+    /// when a client rate limit is configured, retrying it according to the retry policy when
+    /// `replay` allows.
+    async fn send<T, E, Fut>(
+        &self,
+        operation: &'static str,
+        replay: Replay,
+        request: impl Fn() -> Fut,
+    ) -> Result<T, Error>
+    where
+        Fut: Future<Output = Result<T, crate::openapi::apis::Error<E>>>,
+        Error: From<crate::openapi::apis::Error<E>>,
+    {
+        let request = &request;
+        // Each attempt is boxed, since the layers wrapped around the request would otherwise
+        // copy it into every caller's future.
+        call_options::retrying(self.retry, replay, |attempt| {
+            Box::pin(async move {
+                let request = telemetry::http(operation, attempt, request());
+                let request = client_limiter::send(
+                    self.client_limiter.as_deref(),
+                    Priority::Interactive,
+                    request,
+                );
+                match &self.circuit_breaker {
+                    Some(breaker) => breaker.call(request).await,
+                    None => request.await,
+                }
+            })
+        })
         .await
-        .map(|response| response.external_id)
-        .map_err(Into::into)
+    }
+
+    /// The state of the circuit breaker for authentication requests, if one is configured.
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker.as_ref().map(|breaker| breaker.state())
+    }
+
+    /// Runs a request to Passage unless one of its throttle keys is locked out, counting the
+    /// request's failure against them.
+    async fn throttled<T>(
+        &self,
+        external_id: Option<&str>,
+        caller: Option<&str>,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let Some(throttle) = &self.throttle else {
+            return request.await;
+        };
+
+        let keys = Throttle::keys(external_id, caller);
+        throttle.check(&keys)?;
+        let result = request.await;
+        if let Err(e) = &result {
+            if Throttle::counts(e) {
+                throttle.record_failure(&keys);
+            }
+        }
+        result
+    }
+
+    fn transaction_store(&self) -> Result<&(Arc<dyn TransactionStore>, Duration), Error> {
+        self.transaction_store
+            .as_ref()
+            .ok_or_else(|| Error::Other("no transaction store is configured".to_string()))
+    }
+
+    /// Records a session for a verified nonce in the configured session store.
+    ///
+    /// The session remembers the device likely used for the ceremony when the nonce was verified
+    /// with `VerifyOptions::resolve_details`.
+    ///
+    /// # Arguments
+    ///
+    /// * `verified` - The result of `verify_nonce_detailed`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `Session` or an `Error`.
+    pub async fn start_session(&self, verified: &VerifiedNonce) -> Result<Session, Error> {
+        Operation::start("auth.start_session", Some(&verified.external_id))
+            .run(self.start_session_untraced(verified))
+            .await
+    }
+
+    /// Like `Auth::start_session`, with options for this call.
+    pub async fn start_session_with_options(
+        &self,
+        verified: &VerifiedNonce,
+        call_options: CallOptions,
+    ) -> Result<Session, Error> {
+        call_options.run(self.start_session(verified)).await
+    }
+
+    async fn start_session_untraced(&self, verified: &VerifiedNonce) -> Result<Session, Error> {
+        let (store, ttl) = self.session_store()?;
+        let created_at = Utc::now();
+        let session = Session {
+            id: uuid::Uuid::new_v4().to_string(),
+            external_id: verified.external_id.clone(),
+            device_id: verified
+                .likely_device
+                .as_ref()
+                .map(|device| device.id.clone()),
+            created_at,
+            expires_at: created_at
+                .checked_add_signed(
+                    chrono::Duration::from_std(*ttl).unwrap_or(chrono::Duration::MAX),
+                )
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            revoked_at: None,
+        };
+        store.create(session.clone()).await?;
+        Ok(session)
+    }
+
+    /// Checks that a session recorded with `start_session` is still active.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - The ID of the session.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Session` or an `Error`. Unknown sessions return
+    /// `Error::SessionNotFound`, expired ones `Error::SessionExpired`, and revoked ones
+    /// `Error::SessionRevoked`.
+    pub async fn validate_session(&self, session_id: &str) -> Result<Session, Error> {
+        Operation::start("auth.validate_session", None)
+            .run(self.validate_session_untraced(session_id))
+            .await
+    }
+
+    /// Like `Auth::validate_session`, with options for this call.
+    pub async fn validate_session_with_options(
+        &self,
+        session_id: &str,
+        call_options: CallOptions,
+    ) -> Result<Session, Error> {
+        call_options.run(self.validate_session(session_id)).await
+    }
+
+    async fn validate_session_untraced(&self, session_id: &str) -> Result<Session, Error> {
+        if session_id.is_empty() {
+            return Err(Error::InvalidArgument("session_id is required".to_string()));
+        }
+
+        let (store, _) = self.session_store()?;
+        let session = store.get(session_id).await?.ok_or(Error::SessionNotFound)?;
+
+        if session.revoked_at.is_some() {
+            return Err(Error::SessionRevoked);
+        }
+
+        if !session.is_active() {
+            return Err(Error::SessionExpired);
+        }
+
+        Ok(session)
+    }
+
+    /// Lists a user's sessions that have neither expired nor been revoked.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the active sessions or an `Error`.
+    pub async fn list_sessions(&self, external_id: &str) -> Result<Vec<Session>, Error> {
+        Operation::start("auth.list_sessions", Some(external_id))
+            .run(self.list_sessions_untraced(external_id))
+            .await
+    }
+
+    /// Like `Auth::list_sessions`, with options for this call.
+    pub async fn list_sessions_with_options(
+        &self,
+        external_id: &str,
+        call_options: CallOptions,
+    ) -> Result<Vec<Session>, Error> {
+        call_options.run(self.list_sessions(external_id)).await
+    }
+
+    async fn list_sessions_untraced(&self, external_id: &str) -> Result<Vec<Session>, Error> {
+        if external_id.is_empty() {
+            return Err(Error::InvalidArgument(
+                "external_id is required".to_string(),
+            ));
+        }
+
+        let (store, _) = self.session_store()?;
+        store.list_active(external_id).await
+    }
+
+    /// Revokes a single session, such as when the user signs out.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - The ID of the session.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if the session was revoked, or `false` if it was unknown or
+    /// already revoked, or an `Error`.
+    pub async fn revoke_session(&self, session_id: &str) -> Result<bool, Error> {
+        Operation::start("auth.revoke_session", None)
+            .run(self.revoke_session_untraced(session_id))
+            .await
+    }
+
+    /// Like `Auth::revoke_session`, with options for this call.
+    pub async fn revoke_session_with_options(
+        &self,
+        session_id: &str,
+        call_options: CallOptions,
+    ) -> Result<bool, Error> {
+        call_options.run(self.revoke_session(session_id)).await
+    }
+
+    async fn revoke_session_untraced(&self, session_id: &str) -> Result<bool, Error> {
+        if session_id.is_empty() {
+            return Err(Error::InvalidArgument("session_id is required".to_string()));
+        }
+
+        let (store, _) = self.session_store()?;
+        store.revoke(session_id).await
+    }
+
+    fn session_store(&self) -> Result<&(Arc<dyn SessionStore>, Duration), Error> {
+        self.session_store
+            .as_ref()
+            .ok_or_else(|| Error::Other("no session store is configured".to_string()))
+    }
+
+    /// Starts a step-up for a sensitive action by creating an authenticate transaction bound to
+    /// the action.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    /// * `action` - The name of the pending action, such as `"delete_account"`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `StepUpChallenge` or an `Error`.
+    pub async fn begin_step_up(
+        &self,
+        external_id: String,
+        action: &str,
+    ) -> Result<StepUpChallenge, Error> {
+        Operation::start("auth.begin_step_up", Some(&external_id))
+            .run(self.begin_step_up_untraced(external_id, action))
+            .await
+    }
+
+    /// Like `Auth::begin_step_up`, with options for this call.
+    pub async fn begin_step_up_with_options(
+        &self,
+        external_id: String,
+        action: &str,
+        call_options: CallOptions,
+    ) -> Result<StepUpChallenge, Error> {
+        call_options
+            .run(self.begin_step_up(external_id, action))
+            .await
+    }
+
+    async fn begin_step_up_untraced(
+        &self,
+        external_id: String,
+        action: &str,
+    ) -> Result<StepUpChallenge, Error> {
+        if action.is_empty() {
+            return Err(Error::InvalidArgument("action is required".to_string()));
+        }
+
+        let step_up = self.step_up()?;
+        let challenge_id = uuid::Uuid::new_v4().to_string();
+        let mut transaction = self
+            .request_authenticate_transaction(external_id.clone(), None)
+            .await?;
+        if self.transaction_store.is_some() {
+            let binding = step_up_binding(&transaction.id, &challenge_id);
+            transaction = self.track_transaction(transaction, &binding).await?;
+        }
+
+        step_up.challenge(transaction, &challenge_id, &external_id, action)
+    }
+
+    /// Completes a step-up by verifying the nonce from the challenge's ceremony.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonce` - The nonce string to be verified.
+    /// * `challenge_token` - The `token` of the `StepUpChallenge` returned by `begin_step_up`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `StepUpProof` or an `Error`. A tampered or expired challenge
+    /// returns `Error::InvalidStepUp`, and a nonce for a different user
+    /// `Error::TransactionMismatch`. With a transaction store, a challenge that was already
+    /// completed returns `Error::TransactionNotFound`.
+    pub async fn complete_step_up(
+        &self,
+        nonce: String,
+        challenge_token: &str,
+    ) -> Result<StepUpProof, Error> {
+        Operation::start("auth.complete_step_up", None)
+            .run(self.complete_step_up_untraced(nonce, challenge_token))
+            .await
+    }
+
+    /// Like `Auth::complete_step_up`, with options for this call.
+    pub async fn complete_step_up_with_options(
+        &self,
+        nonce: String,
+        challenge_token: &str,
+        call_options: CallOptions,
+    ) -> Result<StepUpProof, Error> {
+        call_options
+            .run(self.complete_step_up(nonce, challenge_token))
+            .await
+    }
+
+    async fn complete_step_up_untraced(
+        &self,
+        nonce: String,
+        challenge_token: &str,
+    ) -> Result<StepUpProof, Error> {
+        let step_up = self.step_up()?;
+        let challenge = step_up.open_challenge(challenge_token)?;
+
+        let external_id = if self.transaction_store.is_some() {
+            self.verify_tracked_nonce(
+                nonce,
+                &challenge.transaction_id,
+                &step_up_binding(&challenge.transaction_id, &challenge.challenge_id),
+            )
+            .await?
+        } else {
+            self.verify_nonce(nonce).await?
+        };
+        telemetry::record_external_id(&external_id);
+
+        if external_id != challenge.external_id {
+            return Err(Error::TransactionMismatch);
+        }
+
+        step_up.proof(&external_id, &challenge.action)
+    }
+
+    /// Checks that a user completed a step-up for an action within its freshness window.
+    /// Call this in the action handler before performing the action.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof_token` - The `token` of the `StepUpProof` returned by `complete_step_up`.
+    /// * `external_id` - The user performing the action.
+    /// * `action` - The action being performed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `StepUpProof` or `Error::InvalidStepUp`.
+    pub fn verify_step_up(
+        &self,
+        proof_token: &str,
+        external_id: &str,
+        action: &str,
+    ) -> Result<StepUpProof, Error> {
+        self.step_up()?
+            .verify_proof(proof_token, external_id, action)
+    }
+
+    fn step_up(&self) -> Result<&StepUp, Error> {
+        self.step_up
+            .as_ref()
+            .ok_or_else(|| Error::Other("step-up is not configured".to_string()))
+    }
+
+    /// Verifies the nonce received from a WebAuthn registration or authentication ceremony and
+    /// optionally guesses what the ceremony was. See `VerifiedNonce` for how reliable the guess
+    /// is.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonce` - The nonce string to be verified.
+    /// * `options` - Which details to resolve in addition to the external ID.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `VerifiedNonce` or an `Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::models::{TransactionKind, VerifyOptions};
+    /// use passage_flex::PassageFlex;
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// );
+    ///
+    /// let verified = passage_flex
+    ///     .auth
+    ///     .verify_nonce_detailed(
+    ///         "01234567890123456789".to_string(),
+    ///         VerifyOptions {
+    ///             resolve_details: true,
+    ///             ..Default::default()
+    ///         },
+    ///     )
+    ///     .await
+    ///     .unwrap();
+    /// if verified.likely_kind == Some(TransactionKind::Register) {
+    ///     // a new passkey was most likely just created
+    /// }
+    /// ```
+    pub async fn verify_nonce_detailed(
+        &self,
+        nonce: String,
+        options: VerifyOptions,
+    ) -> Result<VerifiedNonce, Error> {
+        Operation::start("auth.verify_nonce_detailed", None)
+            .run(self.verify_nonce_detailed_untraced(nonce, options))
+            .await
+    }
+
+    /// Like `Auth::verify_nonce_detailed`, with options for this call.
+    pub async fn verify_nonce_detailed_with_options(
+        &self,
+        nonce: String,
+        options: VerifyOptions,
+        call_options: CallOptions,
+    ) -> Result<VerifiedNonce, Error> {
+        call_options
+            .run(self.verify_nonce_detailed(nonce, options))
+            .await
+    }
+
+    async fn verify_nonce_detailed_untraced(
+        &self,
+        nonce: String,
+        options: VerifyOptions,
+    ) -> Result<VerifiedNonce, Error> {
+        let external_id = self.verify_nonce(nonce).await?;
+        telemetry::record_external_id(&external_id);
+        let mut verified = VerifiedNonce {
+            external_id,
+            likely_kind: None,
+            verified_at: Utc::now(),
+            likely_device: None,
+            user: None,
+        };
+
+        if !options.resolve_details && !options.fetch_user {
+            return Ok(verified);
+        }
+
+        let user = self.lookups();
+        let user_id = user.get_id(verified.external_id.clone()).await?;
+        let info = user.get_info(&user_id).await?;
+
+        if options.resolve_details {
+            verified.likely_kind = likely_ceremony_kind(&info);
+            verified.likely_device = likely_ceremony_device(&info, verified.likely_kind);
+        }
+        if options.fetch_user {
+            verified.user = Some(Box::new(PassageUser::from(info)));
+        }
+
+        Ok(verified)
+    }
+}
+
+/// `Auth` operations whose failures also count against a caller key. Created by
+/// `Auth::for_caller`.
+pub struct CallerAuth<'a> {
+    auth: &'a Auth,
+    caller_key: &'a str,
+}
+
+impl CallerAuth<'_> {
+    /// Like `Auth::create_register_transaction`, refused while the caller is locked out.
+    pub async fn create_register_transaction(
+        &self,
+        external_id: String,
+        passkey_display_name: String,
+    ) -> Result<Transaction, Error> {
+        self.auth
+            .create_register_transaction_as(
+                external_id,
+                passkey_display_name,
+                Some(self.caller_key),
+            )
+            .await
+    }
+
+    /// Like `CallerAuth::create_register_transaction`, with options for this call.
+    pub async fn create_register_transaction_with_options(
+        &self,
+        external_id: String,
+        passkey_display_name: String,
+        call_options: CallOptions,
+    ) -> Result<Transaction, Error> {
+        call_options
+            .run(self.create_register_transaction(external_id, passkey_display_name))
+            .await
+    }
+
+    /// Like `Auth::create_authenticate_transaction`, refused while the caller is locked out.
+    pub async fn create_authenticate_transaction(
+        &self,
+        external_id: String,
+    ) -> Result<Transaction, Error> {
+        self.auth
+            .create_authenticate_transaction_as(external_id, Some(self.caller_key))
+            .await
+    }
+
+    /// Like `CallerAuth::create_authenticate_transaction`, with options for this call.
+    pub async fn create_authenticate_transaction_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<Transaction, Error> {
+        call_options
+            .run(self.create_authenticate_transaction(external_id))
+            .await
+    }
+
+    /// Like `Auth::verify_nonce`, counting failures against the caller.
+    pub async fn verify_nonce(&self, nonce: String) -> Result<String, Error> {
+        self.auth
+            .verify_nonce_as(nonce, None, Some(self.caller_key))
+            .await
+    }
+
+    /// Like `CallerAuth::verify_nonce`, with options for this call.
+    pub async fn verify_nonce_with_options(
+        &self,
+        nonce: String,
+        call_options: CallOptions,
+    ) -> Result<String, Error> {
+        call_options.run(self.verify_nonce(nonce)).await
+    }
+}
+
+/// The session binding used to track a step-up transaction, unique to one challenge.
+fn step_up_binding(transaction_id: &str, challenge_id: &str) -> String {
+    format!("step-up:{}:{}", transaction_id, challenge_id)
+}
+
+/// The kind of the user's most recent completed ceremony, which is likely the one just verified.
+fn likely_ceremony_kind(info: &UserInfo) -> Option<TransactionKind> {
+    info.recent_events
+        .iter()
+        .filter(|event| event.status == UserEventStatus::Complete)
+        .max_by_key(|event| parse_time(&event.created_at))
+        .and_then(|event| match event.action {
+            UserEventAction::Register => Some(TransactionKind::Register),
+            UserEventAction::Login => Some(TransactionKind::Authenticate),
+            UserEventAction::Other => None,
+        })
+}
+
+/// The device most recently created (for registrations) or used (for authentications), which
+/// is likely the one used for the ceremony just verified.
+fn likely_ceremony_device(
+    info: &UserInfo,
+    kind: Option<TransactionKind>,
+) -> Option<WebAuthnDevices> {
+    info.webauthn_devices
+        .iter()
+        .max_by_key(|device| match kind {
+            Some(TransactionKind::Register) => parse_time(&device.created_at),
+            _ => parse_time(&device.last_login_at),
+        })
+        .cloned()
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::step_up::StepUpPolicy;
+    use crate::test_server::{
+        device_created_at, recent_event, user_response_with_events, users_page, Request, TestServer,
+    };
+
+    const USER_NOT_FOUND: &str = r#"{"code":"user_not_found","error":"user not found"}"#;
+    const NO_PASSKEYS: &str = r#"{"code":"user_has_no_passkeys","error":"no passkeys"}"#;
+    const INVALID_NONCE: &str = r#"{"code":"invalid_nonce","error":"invalid nonce"}"#;
+
+    /// Creates transactions and verifies nonces for `user-1`, whose last ceremony was a
+    /// registration of the `new` device. Authenticating `unknown` fails with user not found,
+    /// `no-passkeys` with no passkeys, and the nonce `bad` is invalid.
+    async fn passage(request: Request) -> (u16, String) {
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap_or_default();
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/authenticate/verify") if body["nonce"] == "bad" => {
+                (401, INVALID_NONCE.to_string())
+            }
+            ("POST", "/authenticate/verify") => (200, r#"{"external_id":"user-1"}"#.to_string()),
+            ("POST", "/transactions/authenticate") if body["external_id"] == "unknown" => {
+                (404, USER_NOT_FOUND.to_string())
+            }
+            ("POST", "/transactions/authenticate") if body["external_id"] == "no-passkeys" => {
+                (409, NO_PASSKEYS.to_string())
+            }
+            ("POST", "/transactions/authenticate") => {
+                (200, r#"{"transaction_id":"txn-auth"}"#.to_string())
+            }
+            ("POST", "/transactions/register") => {
+                (200, r#"{"transaction_id":"txn-register"}"#.to_string())
+            }
+            ("GET", path) if path.contains("identifier=unknown") => (200, users_page(&[], 0)),
+            ("GET", path) if path.contains("identifier=no-passkeys") => {
+                (200, users_page(&["no-passkeys"], 1))
+            }
+            ("GET", "/users/id-no-passkeys") => (
+                200,
+                user_response_with_events(
+                    "no-passkeys",
+                    serde_json::json!([]),
+                    serde_json::json!([]),
+                ),
+            ),
+            ("GET", path) if path.starts_with("/users?") => (200, users_page(&["user-1"], 1)),
+            ("GET", "/users/id-user-1") => (
+                200,
+                user_response_with_events(
+                    "user-1",
+                    serde_json::json!([
+                        device_created_at("old", "2024-01-01T00:00:00Z", 9, "2024-03-01T00:00:00Z"),
+                        device_created_at("new", "2024-02-01T00:00:00Z", 0, "2024-02-01T00:00:00Z"),
+                    ]),
+                    serde_json::json!([
+                        recent_event("login", "complete", "2024-01-15T00:00:00Z"),
+                        recent_event("register", "complete", "2024-02-01T00:00:00Z"),
+                        recent_event("login", "incomplete", "2024-02-02T00:00:00Z"),
+                    ]),
+                ),
+            ),
+            _ => (404, "{}".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_nonce_detailed_makes_no_extra_requests_by_default() {
+        let server = TestServer::start(passage).await;
+        let auth = Auth::new(server.configuration());
+
+        let verified = auth
+            .verify_nonce_detailed("nonce".to_string(), VerifyOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(verified.external_id, "user-1");
+        assert_eq!(verified.likely_kind, None);
+        assert_eq!(verified.likely_device, None);
+        assert!(verified.user.is_none());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn verify_nonce_detailed_guesses_the_ceremony_when_asked() {
+        let server = TestServer::start(passage).await;
+        let auth = Auth::new(server.configuration());
+
+        let verified = auth
+            .verify_nonce_detailed(
+                "nonce".to_string(),
+                VerifyOptions {
+                    resolve_details: true,
+                    fetch_user: true,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(verified.likely_kind, Some(TransactionKind::Register));
+        assert_eq!(verified.likely_device.unwrap().id, "new");
+        assert_eq!(verified.user.unwrap().external_id, "user-1");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn discoverable_transactions_omit_the_external_id() {
+        let server = TestServer::start(passage).await;
+        let auth = Auth::new(server.configuration());
+
+        let transaction = auth
+            .create_discoverable_authenticate_transaction()
+            .await
+            .unwrap();
+        assert_eq!(transaction.id, "txn-auth");
+        assert_eq!(transaction.external_id, None);
+        assert!(transaction.matches("anyone"));
+
+        let transaction = auth
+            .create_authenticate_transaction("user-1".to_string())
+            .await
+            .unwrap();
+        assert_eq!(transaction.external_id.as_deref(), Some("user-1"));
+
+        let bodies: Vec<String> = server.requests().into_iter().map(|r| r.body).collect();
+        assert_eq!(bodies, ["{}", r#"{"external_id":"user-1"}"#]);
+    }
+
+    #[tokio::test]
+    async fn enumeration_protection_returns_the_same_transaction_to_everyone() {
+        use futures::StreamExt;
+
+        let server = TestServer::start(passage).await;
+        let mut auth = Auth::new(server.configuration());
+        let (protection, mut audit) = EnumerationProtection::new(Duration::from_millis(50));
+        auth.enumeration_protection = Some(protection);
+
+        for (external_id, outcome) in [
+            ("user-1", AuditOutcome::Found),
+            ("unknown", AuditOutcome::UserNotFound),
+            ("no-passkeys", AuditOutcome::UserHasNoPasskeys),
+        ] {
+            let started_at = tokio::time::Instant::now();
+            let transaction = auth
+                .create_authenticate_transaction(external_id.to_string())
+                .await
+                .unwrap();
+            assert!(started_at.elapsed() >= Duration::from_millis(50));
+            assert_eq!(transaction.external_id.as_deref(), Some(external_id));
+
+            let requests = server.requests();
+            assert_eq!(requests.last().unwrap().body, "{}");
+
+            let event = audit.next().await.unwrap();
+            assert_eq!(event.external_id, external_id);
+            assert_eq!(event.transaction_id, transaction.id);
+            assert_eq!(event.outcome, outcome);
+            assert_eq!(event.decoy, outcome != AuditOutcome::Found);
+        }
+        let posts: Vec<String> = server
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == "POST")
+            .map(|request| request.body)
+            .collect();
+        assert_eq!(posts, ["{}", "{}", "{}"]);
+    }
+
+    #[tokio::test]
+    async fn enumeration_protection_creates_one_transaction_and_caches_lookups() {
+        let server = TestServer::start(passage).await;
+        let mut auth = Auth::new(server.configuration());
+        auth.enumeration_protection = Some(EnumerationProtection::new(Duration::ZERO).0);
+        auth.cache = Some((
+            Arc::new(crate::cache::MemoryCache::new(10)),
+            Duration::from_secs(60),
+        ));
+        auth.rate_limiter = Some(RateLimiter::new(
+            crate::rate_limit::RateLimits::new().per_user(
+                TransactionKind::Authenticate,
+                crate::rate_limit::Rate::new(2, Duration::from_secs(3600)),
+            ),
+        ));
+
+        for _ in 0..2 {
+            auth.create_authenticate_transaction("user-1".to_string())
+                .await
+                .unwrap();
+        }
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths.len(), 4);
+        assert!(paths[0].starts_with("/users?"));
+        assert_eq!(
+            paths[1..],
+            [
+                "/users/id-user-1",
+                "/transactions/authenticate",
+                "/transactions/authenticate"
+            ]
+        );
+
+        assert!(matches!(
+            auth.create_authenticate_transaction("user-1".to_string())
+                .await,
+            Err(Error::RateLimited { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn enumeration_protection_passes_other_errors_through() {
+        let server = TestServer::start(|_| async {
+            (
+                500,
+                r#"{"code":"internal_server_error","error":"boom"}"#.to_string(),
+            )
+        })
+        .await;
+        let mut auth = Auth::new(server.configuration());
+        auth.enumeration_protection = Some(EnumerationProtection::new(Duration::ZERO).0);
+
+        assert!(matches!(
+            auth.create_authenticate_transaction("user-1".to_string())
+                .await,
+            Err(Error::InternalServerError)
+        ));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    async fn begin(auth: &Auth, external_id: &str) -> Result<Ceremony, Error> {
+        auth.begin(external_id.to_string(), "passkey".to_string())
+            .await
+    }
+
+    #[tokio::test]
+    async fn begin_authenticates_users_with_passkeys() {
+        let server = TestServer::start(passage).await;
+        let auth = Auth::new(server.configuration());
+
+        let ceremony = begin(&auth, "user-1").await.unwrap();
+        assert!(matches!(&ceremony, Ceremony::Authenticate(_)));
+        assert_eq!(ceremony.transaction().id, "txn-auth");
+    }
+
+    #[tokio::test]
+    async fn begin_registers_when_the_policy_allows() {
+        let server = TestServer::start(passage).await;
+        let auth = Auth::new(server.configuration());
+
+        for external_id in ["unknown", "no-passkeys"] {
+            let ceremony = begin(&auth, external_id).await.unwrap();
+            assert!(matches!(&ceremony, Ceremony::Register(_)));
+            assert_eq!(ceremony.transaction().id, "txn-register");
+        }
+    }
+
+    #[tokio::test]
+    async fn begin_returns_the_authentication_error_when_registration_is_refused() {
+        let server = TestServer::start(passage).await;
+        let mut auth = Auth::new(server.configuration());
+        auth.registration_policy = RegistrationPolicy::Never;
+        assert!(matches!(
+            begin(&auth, "unknown").await,
+            Err(Error::UserNotFound)
+        ));
+
+        auth.registration_policy =
+            RegistrationPolicy::when(|external_id| async move { Ok(external_id == "unknown") });
+        assert!(matches!(
+            begin(&auth, "unknown").await,
+            Ok(Ceremony::Register(_))
+        ));
+        assert!(matches!(
+            begin(&auth, "no-passkeys").await,
+            Err(Error::UserHasNoPasskeys)
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_bad_nonces_lock_users_out() {
+        let server = TestServer::start(passage).await;
+        let mut auth = Auth::new(server.configuration());
+        auth.throttle = Some(Throttle::new(crate::throttle::ThrottlePolicy::new(
+            2,
+            Duration::from_secs(60),
+        )));
+
+        for _ in 0..3 {
+            let ceremony = begin(&auth, "unknown").await.unwrap();
+            assert!(matches!(ceremony, Ceremony::Register(_)));
+        }
+        for _ in 0..3 {
+            assert!(auth
+                .for_caller("10.0.0.1")
+                .create_authenticate_transaction("no-passkeys".to_string())
+                .await
+                .is_err());
+        }
+        assert!(auth
+            .for_caller("10.0.0.1")
+            .create_authenticate_transaction("user-1".to_string())
+            .await
+            .is_ok());
+
+        for _ in 0..2 {
+            assert!(matches!(
+                auth.for_caller("10.0.0.2")
+                    .verify_nonce("bad".to_string())
+                    .await,
+                Err(Error::InvalidNonce)
+            ));
+        }
+        assert!(matches!(
+            auth.for_caller("10.0.0.2")
+                .verify_nonce("nonce".to_string())
+                .await,
+            Err(Error::TemporarilyLocked { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn begin_requires_a_display_name() {
+        let auth = Auth::new(Configuration::default());
+        assert!(matches!(
+            auth.begin("user-1".to_string(), String::new()).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn nonce_store_rejects_replayed_nonces() {
+        let server = TestServer::start(passage).await;
+        let mut auth = Auth::new(server.configuration());
+        auth.nonce_store = Some(Arc::new(crate::nonce_store::MemoryNonceStore::new(
+            Duration::from_secs(60),
+        )));
+
+        let external_id = auth.verify_nonce("nonce".to_string()).await.unwrap();
+        assert_eq!(external_id, "user-1");
+        assert!(matches!(
+            auth.verify_nonce("nonce".to_string()).await,
+            Err(Error::NonceReplayed)
+        ));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_nonces_are_not_recorded() {
+        let server = TestServer::start(passage).await;
+        let mut auth = Auth::new(server.configuration());
+        auth.nonce_store = Some(Arc::new(crate::nonce_store::MemoryNonceStore::new(
+            Duration::from_secs(60),
+        )));
+
+        for _ in 0..2 {
+            assert!(matches!(
+                auth.verify_nonce("bad".to_string()).await,
+                Err(Error::InvalidNonce)
+            ));
+        }
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    fn tracking_auth(server: &TestServer, ttl: Duration) -> Auth {
+        let mut auth = Auth::new(server.configuration());
+        auth.transaction_store = Some((
+            Arc::new(crate::transaction_tracker::MemoryTransactionStore::new()),
+            ttl,
+        ));
+        auth
+    }
+
+    fn transaction_for(external_id: Option<&str>) -> Transaction {
+        Transaction::new(
+            "txn".to_string(),
+            TransactionKind::Authenticate,
+            String::new(),
+            external_id.map(str::to_string),
+        )
+    }
+
+    fn step_up_auth(server: &TestServer) -> Auth {
+        let mut auth = tracking_auth(server, Duration::from_secs(60));
+        auth.step_up = Some(
+            StepUp::new(
+                b"step-up-secret-step-up-secret-32b",
+                StepUpPolicy::new(Duration::from_secs(60)),
+            )
+            .unwrap(),
+        );
+        auth
+    }
+
+    #[tokio::test]
+    async fn step_up_challenges_complete_once() {
+        let server = TestServer::start(passage).await;
+        let auth = step_up_auth(&server);
+
+        let challenge = auth
+            .begin_step_up("user-1".to_string(), "delete_account")
+            .await
+            .unwrap();
+        let proof = auth
+            .complete_step_up("nonce".to_string(), &challenge.token)
+            .await
+            .unwrap();
+        assert!(auth
+            .verify_step_up(&proof.token, "user-1", "delete_account")
+            .is_ok());
+
+        assert!(matches!(
+            auth.complete_step_up("nonce".to_string(), &challenge.token)
+                .await,
+            Err(Error::TransactionNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn step_up_transactions_are_not_bound_to_public_inputs() {
+        let server = TestServer::start(passage).await;
+        let auth = step_up_auth(&server);
+
+        let challenge = auth
+            .begin_step_up("user-1".to_string(), "delete_account")
+            .await
+            .unwrap();
+        for binding in [
+            "step-up:delete_account:user-1".to_string(),
+            format!("step-up:{}:", challenge.transaction.id),
+        ] {
+            assert!(matches!(
+                auth.verify_tracked_nonce("nonce".to_string(), &challenge.transaction.id, &binding)
+                    .await,
+                Err(Error::TransactionMismatch)
+            ));
+        }
+        assert!(auth
+            .complete_step_up("nonce".to_string(), &challenge.token)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn tracked_nonces_are_redeemed_once_by_the_same_session() {
+        let server = TestServer::start(passage).await;
+        let auth = tracking_auth(&server, Duration::from_secs(60));
+        let transaction = auth
+            .track_transaction(transaction_for(Some("user-1")), "session")
+            .await
+            .unwrap();
+        assert!(transaction.expires_at.is_some());
+
+        assert!(matches!(
+            auth.verify_tracked_nonce("nonce".to_string(), "txn", "other-session")
+                .await,
+            Err(Error::TransactionMismatch)
+        ));
+        let external_id = auth
+            .verify_tracked_nonce("nonce".to_string(), "txn", "session")
+            .await
+            .unwrap();
+        assert_eq!(external_id, "user-1");
+        assert!(matches!(
+            auth.verify_tracked_nonce("nonce".to_string(), "txn", "session")
+                .await,
+            Err(Error::TransactionNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn tracked_nonces_must_verify_to_the_transaction_user() {
+        let server = TestServer::start(passage).await;
+        let auth = tracking_auth(&server, Duration::from_secs(60));
+        auth.track_transaction(transaction_for(Some("user-2")), "session")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            auth.verify_tracked_nonce("nonce".to_string(), "txn", "session")
+                .await,
+            Err(Error::TransactionMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_tracked_transactions_are_rejected_without_verifying() {
+        let server = TestServer::start(passage).await;
+        let auth = tracking_auth(&server, Duration::ZERO);
+        auth.track_transaction(transaction_for(None), "session")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            auth.verify_tracked_nonce("nonce".to_string(), "txn", "session")
+                .await,
+            Err(Error::TransactionExpired)
+        ));
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn likely_device_follows_the_likely_kind() {
+        let info = crate::test_server::user_info(
+            "user-1",
+            serde_json::json!([
+                device_created_at("old", "2024-01-01T00:00:00Z", 9, "2024-03-01T00:00:00Z"),
+                device_created_at("new", "2024-02-01T00:00:00Z", 0, "2024-02-01T00:00:00Z"),
+            ]),
+        );
+        assert_eq!(likely_ceremony_kind(&info), None);
+        let device = |kind| likely_ceremony_device(&info, kind).unwrap().id;
+        assert_eq!(device(Some(TransactionKind::Register)), "new");
+        assert_eq!(device(Some(TransactionKind::Authenticate)), "old");
+        assert_eq!(device(None), "old");
+    }
+
+    const SERVER_ERROR: &str = r#"{"code":"internal_server_error","error":"boom"}"#;
+
+    /// An `Auth` whose requests to `passage` fail with a server error until `failures` have
+    /// been answered, and that retries up to three times.
+    async fn flaky_auth(failures: usize) -> (TestServer, Auth) {
+        let answered = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server = TestServer::start(move |request| {
+            let answered = answered.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                match answered < failures {
+                    true => (500, SERVER_ERROR.to_string()),
+                    false => passage(request).await,
+                }
+            }
+        })
+        .await;
+        let mut configuration = server.configuration();
+        configuration.client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(call_options::idempotency_key)
+            .build();
+        let mut auth = Auth::new(configuration);
+        auth.retry = Retry::new(3).with_backoff(Duration::ZERO, Duration::ZERO);
+        (server, auth)
+    }
+
+    #[tokio::test]
+    async fn nonce_verification_is_never_retried() {
+        let (server, auth) = flaky_auth(1).await;
+
+        let result = auth
+            .verify_nonce_with_options(
+                "nonce".to_string(),
+                CallOptions::new().with_idempotency_key("key-1"),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::InternalServerError)));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn transactions_are_only_retried_with_an_idempotency_key() {
+        let (server, auth) = flaky_auth(1).await;
+        let result = auth
+            .create_authenticate_transaction("user-1".to_string())
+            .await;
+        assert!(matches!(result, Err(Error::InternalServerError)));
+        assert_eq!(server.requests().len(), 1);
+
+        let (server, auth) = flaky_auth(1).await;
+        let transaction = auth
+            .create_authenticate_transaction_with_options(
+                "user-1".to_string(),
+                CallOptions::new().with_idempotency_key("key-1"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(transaction.id, "txn-auth");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests
+            .iter()
+            .all(|request| request.header("Idempotency-Key") == Some("key-1")));
     }
 }
@@ -0,0 +1,192 @@
+//! A short-lived read-through cache for users and their devices.
+//!
+//! Settings pages and authorization checks often look up the same user several times within
+//! seconds. With a cache configured, `User::get` and `User::list_devices` keep their results
+//! for a fixed time to live and serve repeated reads from the cache. Any change the same client
+//! makes to a user, such as revoking a device or deactivating the user, invalidates that
+//! user's entries, and `User::invalidate_cache` does so explicitly for changes made elsewhere.
+//!
+//! Entries are stored as JSON through a `CacheBackend`, so a shared cache such as Redis can be
+//! plugged in. Keys are prefixed with the Passage app ID, so clients of different apps can
+//! share a cache. A cache that fails to read or write is bypassed; a failed invalidation is
+//! returned as an error so stale data isn't served silently.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::cache::MemoryCache;
+//! use passage_flex::PassageFlex;
+//! use std::time::Duration;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_cache(MemoryCache::new(10_000), Duration::from_secs(30));
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+
+use crate::telemetry;
+use crate::Error;
+
+/// Stores cached responses as JSON strings.
+pub trait CacheBackend: Send + Sync {
+    /// Returns the value stored under the key, if it hasn't expired.
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>, Error>>;
+
+    /// Stores a value under the key for `ttl`.
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        value: String,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Removes the value stored under the key, if any.
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+struct Entry {
+    value: String,
+    inserted_at: Instant,
+    expires_at: Instant,
+}
+
+/// An in-memory `CacheBackend` holding a bounded number of entries.
+///
+/// When full, expired entries are dropped first, then the oldest ones.
+pub struct MemoryCache {
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl MemoryCache {
+    /// Creates a cache that holds up to `max_entries` entries.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries: max_entries.max(1),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>, Error>> {
+        Box::pin(async move {
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            Ok(entries
+                .get(key)
+                .filter(|entry| entry.expires_at > Instant::now())
+                .map(|entry| entry.value.clone()))
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        value: String,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if entries.len() >= self.max_entries && !entries.contains_key(key) {
+                entries.retain(|_, entry| entry.expires_at > now);
+            }
+            while entries.len() >= self.max_entries && !entries.contains_key(key) {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.inserted_at)
+                    .map(|(key, _)| key.clone());
+                match oldest {
+                    Some(oldest) => entries.remove(&oldest),
+                    None => break,
+                };
+            }
+
+            entries.insert(
+                key.to_string(),
+                Entry {
+                    value,
+                    inserted_at: now,
+                    expires_at: now + ttl,
+                },
+            );
+            telemetry::cache_entries(entries.len());
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries.remove(key);
+            telemetry::cache_entries(entries.len());
+            Ok(())
+        })
+    }
+}
+
+/// The cache key of a user.
+pub(crate) fn user_key(app_id: &str, external_id: &str) -> String {
+    format!("passage:{}:user:{}", app_id, external_id)
+}
+
+/// The cache key of a user's device list.
+pub(crate) fn devices_key(app_id: &str, external_id: &str) -> String {
+    format!("passage:{}:devices:{}", app_id, external_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn entries_expire_after_their_ttl() {
+        let cache = MemoryCache::new(10);
+        cache
+            .put("a", "1".to_string(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        cache
+            .put("b", "2".to_string(), Duration::ZERO)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.get("a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.get("b").await.unwrap(), None);
+
+        cache.remove("a").await.unwrap();
+        assert_eq!(cache.get("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn a_full_cache_drops_expired_then_oldest_entries() {
+        let cache = MemoryCache::new(2);
+        let ttl = Duration::from_secs(60);
+        cache.put("old", "1".to_string(), ttl).await.unwrap();
+        cache
+            .put("expired", "2".to_string(), Duration::ZERO)
+            .await
+            .unwrap();
+
+        cache.put("new", "3".to_string(), ttl).await.unwrap();
+        assert_eq!(cache.get("old").await.unwrap().as_deref(), Some("1"));
+
+        cache.put("newer", "4".to_string(), ttl).await.unwrap();
+        assert_eq!(cache.get("old").await.unwrap(), None);
+        assert_eq!(cache.get("new").await.unwrap().as_deref(), Some("3"));
+        assert_eq!(cache.get("newer").await.unwrap().as_deref(), Some("4"));
+    }
+
+    #[test]
+    fn keys_are_scoped_to_the_app() {
+        assert_ne!(user_key("app-1", "user-1"), user_key("app-2", "user-1"));
+        assert_ne!(user_key("app-1", "user-1"), devices_key("app-1", "user-1"));
+    }
+}
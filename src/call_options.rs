@@ -0,0 +1,439 @@
+//! Per-call timeouts, deadlines, retries and idempotency keys.
+//!
+//! Every `Auth` and `User` method uses the client's defaults: no deadline beyond the HTTP
+//! client's own timeouts, and the retry policy set with `PassageFlex::with_retry`. Each method
+//! has a `_with_options` variant, such as `Auth::verify_nonce_with_options`, that takes
+//! `CallOptions` overriding them for that call and every request it makes, so the login path
+//! can give nonce verification a tight deadline while a bulk export waits as long as it needs.
+//! A call that runs past its deadline fails with `Error::DeadlineExceeded`.
+//!
+//! Retries only repeat requests that failed with a transient error (see `Error::is_transient`),
+//! never a request rejected by an open circuit breaker, and are skipped when the wait before
+//! the next attempt would run past the deadline. Each attempt goes through the rate limiter
+//! and circuit breaker again. Reads, deletes and activation changes are retried. Creating a
+//! transaction or user is only retried when the call has an idempotency key, which is sent as
+//! the `Idempotency-Key` header on every write request the call makes and stays the same
+//! across retries. Passage doesn't document support for the header, so a retried write may
+//! still take effect twice. Verifying a nonce is never retried, since the failed attempt may
+//! have consumed it.
+//!
+//! Options are passed to the call and apply to everything it does on the task that awaits it.
+//! They don't follow work the caller spawns onto other tasks; pass the options to each call
+//! made there. A call with options doesn't share coalesced reads with other callers.
+//!
+//! # Cancellation
+//!
+//! Dropping a call, either directly or because its deadline passed, stops it at its next await
+//! point and aborts any request in flight. This leaves the client in a consistent state:
+//!
+//! * A circuit breaker probe that was in flight is released, so the breaker can probe again.
+//! * A coalesced read that was in flight is handed to one of the callers waiting on it.
+//! * Rate limit and client rate limit tokens taken by the call stay spent.
+//! * A request that was already sent may still have taken effect at Passage. A transaction or
+//!   user may have been created, and a verified nonce may have been consumed without being
+//!   recorded in the nonce store.
+//! * A response that was never returned is never cached, and cache invalidations that didn't
+//!   run leave entries to expire with their time to live.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::call_options::{CallOptions, Retry};
+//! use passage_flex::{Error, PassageFlex};
+//! use std::time::Duration;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! );
+//!
+//! let options = CallOptions::new()
+//!     .with_timeout(Duration::from_millis(800))
+//!     .with_retry(Retry::new(2));
+//! let verified = passage_flex
+//!     .auth
+//!     .verify_nonce_with_options(nonce, options)
+//!     .await;
+//! match verified {
+//!     Ok(external_id) => {
+//!         // the user is authenticated
+//!     }
+//!     Err(Error::DeadlineExceeded) => {
+//!         // ask the user to try again
+//!     }
+//!     Err(err) => {
+//!         // nonce was invalid or unable to be verified
+//!     }
+//! }
+//! ```
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use reqwest::header::HeaderValue;
+use reqwest_middleware::Next;
+
+use crate::Error;
+
+/// How many times a request to Passage is attempted, and how long to wait between attempts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retry {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl Retry {
+    /// Sends each request once. This is the default.
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    /// Attempts each request up to `max_attempts` times, waiting 100 milliseconds before the
+    /// first retry and doubling the wait for each one after, up to 2 seconds.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+
+    /// Sets the wait before the first retry and the longest wait between attempts.
+    pub fn with_backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay.max(base_delay);
+        self
+    }
+
+    /// The wait after the given attempt failed.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay)
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// Options that override the client's defaults for one call.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallOptions {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    retry: Option<Retry>,
+    idempotency_key: Option<String>,
+}
+
+impl CallOptions {
+    /// Creates options that keep the client's defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails the call with `Error::DeadlineExceeded` if it takes longer than `timeout`,
+    /// measured from when it starts running.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails the call with `Error::DeadlineExceeded` if it hasn't finished by `deadline`, such
+    /// as the deadline of the request your server is handling.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Retries failed requests according to `retry` instead of the client's retry policy.
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Sends `key` as the `Idempotency-Key` header on the call's write requests, and lets
+    /// requests that create a transaction or user be retried.
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Runs an `Auth` or `User` call with these options. This is what the `_with_options`
+    /// methods do, and can also apply the options to several calls awaited together.
+    ///
+    /// When calls with options are nested, the earliest deadline applies, and the innermost
+    /// retry policy and idempotency key.
+    ///
+    /// # Arguments
+    ///
+    /// * `call` - The call, such as `passage_flex.auth.verify_nonce(nonce)`.
+    ///
+    /// # Returns
+    ///
+    /// The call's result, or `Error::DeadlineExceeded` if it ran past the timeout or deadline.
+    /// An idempotency key that isn't a valid header value returns `Error::InvalidArgument`.
+    pub async fn run<T>(self, call: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        if let Some(key) = &self.idempotency_key {
+            if HeaderValue::from_str(key).is_err() {
+                return Err(Error::InvalidArgument(
+                    "idempotency_key is not a valid header value".to_string(),
+                ));
+            }
+        }
+
+        let outer = current();
+        let deadline = [
+            self.timeout.map(|timeout| Instant::now() + timeout),
+            self.deadline,
+            outer.deadline,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let scope = Scope {
+            deadline,
+            retry: self.retry.or(outer.retry),
+            idempotency_key: self.idempotency_key.or(outer.idempotency_key),
+        };
+
+        let call = SCOPE.scope(scope, call);
+        match deadline {
+            Some(deadline) => {
+                tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), call)
+                    .await
+                    .unwrap_or(Err(Error::DeadlineExceeded))
+            }
+            None => call.await,
+        }
+    }
+}
+
+/// The options in effect for the current task.
+#[derive(Clone, Default)]
+struct Scope {
+    deadline: Option<Instant>,
+    retry: Option<Retry>,
+    idempotency_key: Option<String>,
+}
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+fn current() -> Scope {
+    SCOPE.try_with(Scope::clone).unwrap_or_default()
+}
+
+/// Whether the current task is running a call with options. Such calls don't share coalesced
+/// reads, which run with the options of the task that sent them.
+pub(crate) fn in_effect() -> bool {
+    SCOPE.try_with(|_| ()).is_ok()
+}
+
+/// Whether a request can be sent again after an attempt failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Replay {
+    /// Sending the request twice has the same effect as sending it once, as for reads.
+    Safe,
+    /// The request may take effect twice, as for creating a transaction or user. It is only
+    /// retried when the call has an idempotency key.
+    WithIdempotencyKey,
+    /// The request must only be sent once, as for verifying a nonce that the first attempt may
+    /// have consumed.
+    Never,
+}
+
+/// Runs the attempts of a request until one succeeds, fails for good, or the retry policy in
+/// effect is exhausted. `default` is the client's policy, used unless the call overrides it.
+pub(crate) async fn retrying<T, Fut>(
+    default: Retry,
+    replay: Replay,
+    mut attempt: impl FnMut(u32) -> Fut,
+) -> Result<T, Error>
+where
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let scope = current();
+    let retry = scope.retry.unwrap_or(default);
+    let max_attempts = match replay {
+        Replay::Safe => retry.max_attempts,
+        Replay::WithIdempotencyKey if scope.idempotency_key.is_some() => retry.max_attempts,
+        Replay::WithIdempotencyKey | Replay::Never => 1,
+    };
+    let mut number = 1;
+    loop {
+        let result = attempt(number).await;
+        let Err(e) = &result else {
+            return result;
+        };
+        if number >= max_attempts || !e.is_transient() || matches!(e, Error::CircuitOpen) {
+            return result;
+        }
+
+        let delay = retry.delay(number);
+        if scope
+            .deadline
+            .is_some_and(|deadline| Instant::now() + delay >= deadline)
+        {
+            return result;
+        }
+        tokio::time::sleep(delay).await;
+        number += 1;
+    }
+}
+
+/// Middleware that adds the idempotency key in effect to write requests.
+pub(crate) fn idempotency_key<'a>(
+    mut request: reqwest::Request,
+    extensions: &'a mut http::Extensions,
+    next: Next<'a>,
+) -> BoxFuture<'a, reqwest_middleware::Result<reqwest::Response>> {
+    if request.method() != reqwest::Method::GET {
+        if let Some(value) = current()
+            .idempotency_key
+            .and_then(|key| HeaderValue::from_str(&key).ok())
+        {
+            request.headers_mut().insert("Idempotency-Key", value);
+        }
+    }
+    next.run(request, extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn retry(max_attempts: u32) -> Retry {
+        Retry::new(max_attempts).with_backoff(Duration::ZERO, Duration::ZERO)
+    }
+
+    /// Runs `retrying` with attempts that fail with `error` until the last one, returning how
+    /// many attempts were made.
+    async fn attempts(default: Retry, replay: Replay, error: fn() -> Error) -> u32 {
+        let made = AtomicU32::new(0);
+        let _ = retrying(default, replay, |number| {
+            made.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match number {
+                    3 => Ok(()),
+                    _ => Err(error()),
+                }
+            }
+        })
+        .await;
+        made.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn safe_requests_are_retried_on_transient_errors() {
+        let transient = || Error::InternalServerError;
+        assert_eq!(attempts(retry(5), Replay::Safe, transient).await, 3);
+        assert_eq!(attempts(retry(2), Replay::Safe, transient).await, 2);
+        assert_eq!(attempts(Retry::none(), Replay::Safe, transient).await, 1);
+
+        assert_eq!(
+            attempts(retry(5), Replay::Safe, || Error::InvalidNonce).await,
+            1
+        );
+        assert_eq!(
+            attempts(retry(5), Replay::Safe, || Error::CircuitOpen).await,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn call_options_override_the_default_retry_policy() {
+        let options = CallOptions::new().with_retry(Retry::none());
+        let made = options
+            .run(async { Ok(attempts(retry(5), Replay::Safe, || Error::TooManyRequests).await) })
+            .await
+            .unwrap();
+        assert_eq!(made, 1);
+    }
+
+    #[tokio::test]
+    async fn writes_are_only_retried_with_an_idempotency_key() {
+        let transient = || Error::InternalServerError;
+        assert_eq!(
+            attempts(retry(5), Replay::WithIdempotencyKey, transient).await,
+            1
+        );
+
+        let options = CallOptions::new().with_idempotency_key("key-1");
+        let made = options
+            .run(async { Ok(attempts(retry(5), Replay::WithIdempotencyKey, transient).await) })
+            .await
+            .unwrap();
+        assert_eq!(made, 3);
+    }
+
+    #[tokio::test]
+    async fn requests_that_must_be_sent_once_are_never_retried() {
+        let options = CallOptions::new()
+            .with_retry(retry(5))
+            .with_idempotency_key("key-1");
+        let made = options
+            .run(async {
+                Ok(attempts(retry(5), Replay::Never, || Error::InternalServerError).await)
+            })
+            .await
+            .unwrap();
+        assert_eq!(made, 1);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_before_the_deadline() {
+        let options = CallOptions::new()
+            .with_timeout(Duration::from_secs(1))
+            .with_retry(Retry::new(5).with_backoff(Duration::from_secs(2), Duration::from_secs(2)));
+        let made = options
+            .run(async {
+                Ok(attempts(Retry::none(), Replay::Safe, || Error::InternalServerError).await)
+            })
+            .await
+            .unwrap();
+        assert_eq!(made, 1);
+    }
+
+    #[tokio::test]
+    async fn calls_past_their_deadline_fail() {
+        let result = CallOptions::new()
+            .with_timeout(Duration::from_millis(10))
+            .run(futures::future::pending::<Result<(), Error>>())
+            .await;
+        assert!(matches!(result, Err(Error::DeadlineExceeded)));
+
+        let outer = CallOptions::new().with_timeout(Duration::from_millis(10));
+        let inner = CallOptions::new().with_timeout(Duration::from_secs(60));
+        let result = outer
+            .run(inner.run(futures::future::pending::<Result<(), Error>>()))
+            .await;
+        assert!(matches!(result, Err(Error::DeadlineExceeded)));
+    }
+
+    #[tokio::test]
+    async fn invalid_idempotency_keys_are_rejected() {
+        let result = CallOptions::new()
+            .with_idempotency_key("line\nbreak")
+            .run(async { Ok(()) })
+            .await;
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn options_are_in_effect_only_inside_the_call() {
+        assert!(!in_effect());
+        let inside = CallOptions::new()
+            .run(async { Ok(in_effect()) })
+            .await
+            .unwrap();
+        assert!(inside);
+    }
+}
@@ -0,0 +1,325 @@
+//! Circuit breakers that fail fast while Passage is unavailable.
+//!
+//! Without a breaker, every request made while Passage is degraded waits for its full timeout
+//! before failing. With a `CircuitBreakerPolicy` configured, `Auth` and `User` each get a
+//! breaker. After enough consecutive failures (timeouts, connection errors and server errors)
+//! the breaker opens, and requests fail immediately with `Error::CircuitOpen`. Once the open
+//! period has passed, the breaker lets one probe request through at a time; enough successful
+//! probes close it again, and a failed probe reopens it.
+//!
+//! The `Auth` breaker covers every request made while authenticating, including the user
+//! lookups of `Auth::verify_nonce_detailed`, so user management outages don't block logins
+//! until they affect them directly.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::circuit_breaker::{CircuitBreakerPolicy, CircuitState};
+//! use passage_flex::PassageFlex;
+//! use std::time::Duration;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_circuit_breaker(CircuitBreakerPolicy::new(5, Duration::from_secs(30)));
+//!
+//! // in a health check
+//! let healthy = passage_flex.auth.circuit_state() != Some(CircuitState::Open);
+//! ```
+
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::telemetry;
+use crate::Error;
+
+/// When a breaker opens and how it recovers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitBreakerPolicy {
+    failure_threshold: u32,
+    open_duration: Duration,
+    probe_successes: u32,
+}
+
+impl CircuitBreakerPolicy {
+    /// Opens the breaker after `failure_threshold` consecutive failures and keeps it open for
+    /// `open_duration` before probing. One successful probe closes it.
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            probe_successes: 1,
+        }
+    }
+
+    /// Sets how many probes in a row must succeed before the breaker closes.
+    pub fn with_probe_successes(mut self, successes: u32) -> Self {
+        self.probe_successes = successes.max(1);
+        self
+    }
+}
+
+/// The state of a circuit breaker.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum CircuitState {
+    /// Requests are sent normally.
+    Closed,
+    /// Requests fail immediately with `Error::CircuitOpen`.
+    Open,
+    /// Probe requests are being let through to check whether Passage has recovered.
+    HalfOpen,
+}
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { successes: u32, probing: bool },
+}
+
+/// A circuit breaker for one group of endpoints.
+pub(crate) struct CircuitBreaker {
+    name: &'static str,
+    policy: CircuitBreakerPolicy,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    /// Creates a closed breaker, named after the endpoints it covers in metrics.
+    pub(crate) fn new(name: &'static str, policy: CircuitBreakerPolicy) -> Self {
+        telemetry::circuit_state(name, CircuitState::Closed);
+        Self {
+            name,
+            policy,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match *state {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if until > Instant::now() => CircuitState::Open,
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Sends a request unless the breaker is open, recording its outcome.
+    pub(crate) async fn call<T>(
+        &self,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let probe = self.admit()?;
+        let result = request.await;
+        probe.finish(&result);
+        result
+    }
+
+    fn admit(&self) -> Result<Probe<'_>, Error> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match *state {
+            State::Closed { .. } => Ok(Probe {
+                breaker: self,
+                probing: false,
+                finished: false,
+            }),
+            State::Open { until } if until > Instant::now() => Err(Error::CircuitOpen),
+            State::Open { .. } => {
+                *state = State::HalfOpen {
+                    successes: 0,
+                    probing: true,
+                };
+                telemetry::circuit_state(self.name, CircuitState::HalfOpen);
+                Ok(Probe {
+                    breaker: self,
+                    probing: true,
+                    finished: false,
+                })
+            }
+            State::HalfOpen { probing: true, .. } => Err(Error::CircuitOpen),
+            State::HalfOpen {
+                ref mut probing, ..
+            } => {
+                *probing = true;
+                Ok(Probe {
+                    breaker: self,
+                    probing: true,
+                    finished: false,
+                })
+            }
+        }
+    }
+
+    fn record(&self, probing: bool, failed: bool) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let open = State::Open {
+            until: Instant::now() + self.policy.open_duration,
+        };
+        *state = match (&*state, failed) {
+            (State::Closed { .. }, false) => State::Closed { failures: 0 },
+            (State::Closed { failures }, true) if failures + 1 >= self.policy.failure_threshold => {
+                open
+            }
+            (State::Closed { failures }, true) => State::Closed {
+                failures: failures + 1,
+            },
+            (State::HalfOpen { .. }, true) if probing => open,
+            (State::HalfOpen { successes, .. }, false) if probing => {
+                if successes + 1 >= self.policy.probe_successes {
+                    State::Closed { failures: 0 }
+                } else {
+                    State::HalfOpen {
+                        successes: successes + 1,
+                        probing: false,
+                    }
+                }
+            }
+            // A request admitted before the breaker opened finished late.
+            _ => return,
+        };
+
+        let reported = match *state {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        };
+        telemetry::circuit_state(self.name, reported);
+    }
+
+    /// Frees the probe slot of a request that was dropped before it finished.
+    fn abandon(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let State::HalfOpen { probing, .. } = &mut *state {
+            *probing = false;
+        }
+    }
+}
+
+/// A request admitted by a breaker. Dropping it unfinished frees its probe slot, so a
+/// cancelled probe can't hold the breaker half-open forever.
+struct Probe<'a> {
+    breaker: &'a CircuitBreaker,
+    probing: bool,
+    finished: bool,
+}
+
+impl Probe<'_> {
+    fn finish<T>(mut self, result: &Result<T, Error>) {
+        self.finished = true;
+        let failed = matches!(
+            result,
+            Err(e) if e.is_transient() && !matches!(e, Error::TooManyRequests)
+        );
+        self.breaker.record(self.probing, failed);
+    }
+}
+
+impl Drop for Probe<'_> {
+    fn drop(&mut self) {
+        if self.probing && !self.finished {
+            self.breaker.abandon();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(policy: CircuitBreakerPolicy) -> CircuitBreaker {
+        CircuitBreaker::new("test", policy)
+    }
+
+    async fn fail(breaker: &CircuitBreaker) -> Result<(), Error> {
+        breaker
+            .call(async { Err::<(), _>(Error::InternalServerError) })
+            .await
+    }
+
+    async fn succeed(breaker: &CircuitBreaker) -> Result<(), Error> {
+        breaker.call(async { Ok(()) }).await
+    }
+
+    #[tokio::test]
+    async fn opens_after_consecutive_failures() {
+        let breaker = breaker(CircuitBreakerPolicy::new(2, Duration::from_secs(60)));
+
+        fail(&breaker).await.unwrap_err();
+        succeed(&breaker).await.unwrap();
+        fail(&breaker).await.unwrap_err();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        fail(&breaker).await.unwrap_err();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(succeed(&breaker).await, Err(Error::CircuitOpen)));
+    }
+
+    #[tokio::test]
+    async fn caller_errors_and_rate_limits_dont_count() {
+        let breaker = breaker(CircuitBreakerPolicy::new(1, Duration::from_secs(60)));
+
+        for error in [Error::UserNotFound, Error::TooManyRequests] {
+            breaker
+                .call(async { Err::<(), _>(error) })
+                .await
+                .unwrap_err();
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn probes_one_request_at_a_time_until_enough_succeed() {
+        let breaker = breaker(
+            CircuitBreakerPolicy::new(1, Duration::from_millis(10)).with_probe_successes(2),
+        );
+        fail(&breaker).await.unwrap_err();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let probe = breaker.call(async {
+            released.await.unwrap();
+            Ok(())
+        });
+        let concurrent = async {
+            tokio::task::yield_now().await;
+            let result = succeed(&breaker).await;
+            release.send(()).unwrap();
+            result
+        };
+        let (probe, concurrent) = tokio::join!(probe, concurrent);
+        probe.unwrap();
+        assert!(matches!(concurrent, Err(Error::CircuitOpen)));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        succeed(&breaker).await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn a_failed_probe_reopens() {
+        let breaker = breaker(CircuitBreakerPolicy::new(1, Duration::from_millis(10)));
+        fail(&breaker).await.unwrap_err();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        fail(&breaker).await.unwrap_err();
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn a_dropped_probe_frees_its_slot() {
+        let breaker = breaker(CircuitBreakerPolicy::new(1, Duration::from_millis(10)));
+        fail(&breaker).await.unwrap_err();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let probe = breaker.call(futures::future::pending::<Result<(), Error>>());
+        assert!(tokio::time::timeout(Duration::from_millis(10), probe)
+            .await
+            .is_err());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        succeed(&breaker).await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
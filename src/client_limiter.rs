@@ -0,0 +1,230 @@
+//! A client-wide rate limit shared by every request to Passage.
+//!
+//! Passage limits requests per API key, so a bulk job such as a device cleanup can use up the
+//! quota that interactive logins need. With a `ClientRateLimit` configured, every request the
+//! client sends waits for a token from one shared bucket. Requests made through `Auth` are
+//! interactive; those made through `User` are bulk and leave a reserve of tokens that only
+//! interactive requests can take, so logins keep going while a job runs.
+//!
+//! When Passage answers with `429 Too Many Requests`, the limiter halves its rate and pauses
+//! briefly, then recovers gradually as requests succeed again.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::client_limiter::ClientRateLimit;
+//! use passage_flex::PassageFlex;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_client_rate_limit(ClientRateLimit::new(20).with_interactive_reserve(5));
+//! ```
+
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// The slowest the limiter will go, as a fraction of the configured rate.
+const MIN_FACTOR: f64 = 1.0 / 32.0;
+/// How much of the configured rate each successful request restores after a slowdown.
+const RECOVERY_STEP: f64 = 1.0 / 64.0;
+/// How long all requests are held back after Passage answers with `429 Too Many Requests`.
+const PAUSE: Duration = Duration::from_secs(1);
+
+/// Which requests go first when the limit is reached.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Priority {
+    /// Requests made while a user is waiting, such as transaction creation and nonce
+    /// verification.
+    Interactive,
+    /// User management requests, such as those made by background jobs.
+    Bulk,
+}
+
+/// The request rate the client stays under.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClientRateLimit {
+    requests_per_second: f64,
+    burst: u32,
+    interactive_reserve: u32,
+}
+
+impl ClientRateLimit {
+    /// Limits the client to `requests_per_second`, allowing bursts of the same size. A fifth of
+    /// the burst is reserved for interactive requests.
+    pub fn new(requests_per_second: u32) -> Self {
+        let requests_per_second = requests_per_second.max(1);
+        Self {
+            requests_per_second: f64::from(requests_per_second),
+            burst: requests_per_second,
+            interactive_reserve: (requests_per_second / 5).max(1),
+        }
+    }
+
+    /// Sets how many requests can be sent at once after a quiet period.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Sets how many tokens bulk requests must leave for interactive ones.
+    pub fn with_interactive_reserve(mut self, reserve: u32) -> Self {
+        self.interactive_reserve = reserve;
+        self
+    }
+}
+
+struct State {
+    tokens: f64,
+    updated_at: Instant,
+    /// The fraction of the configured rate currently allowed.
+    factor: f64,
+    paused_until: Option<Instant>,
+}
+
+/// Enforces a `ClientRateLimit` across every request to Passage.
+pub(crate) struct ClientLimiter {
+    limit: ClientRateLimit,
+    state: Mutex<State>,
+}
+
+impl ClientLimiter {
+    pub(crate) fn new(limit: ClientRateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(State {
+                tokens: f64::from(limit.burst),
+                updated_at: Instant::now(),
+                factor: 1.0,
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a request of the given priority may be sent.
+    async fn acquire(&self, priority: Priority) {
+        loop {
+            let wait = {
+                let now = Instant::now();
+                let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                let rate = self.limit.requests_per_second * state.factor;
+                let elapsed = now.duration_since(state.updated_at).as_secs_f64();
+                state.tokens = (state.tokens + elapsed * rate).min(f64::from(self.limit.burst));
+                state.updated_at = now;
+
+                let reserve = match priority {
+                    Priority::Interactive => 0.0,
+                    Priority::Bulk => f64::from(self.limit.interactive_reserve)
+                        .min(f64::from(self.limit.burst - 1)),
+                };
+
+                match state.paused_until.filter(|until| *until > now) {
+                    Some(until) => until - now,
+                    None if state.tokens >= reserve + 1.0 => {
+                        state.tokens -= 1.0;
+                        return;
+                    }
+                    None => Duration::from_secs_f64((reserve + 1.0 - state.tokens) / rate),
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Slows down after a `429 Too Many Requests` and speeds back up after successes.
+    fn record<T>(&self, result: &Result<T, Error>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match result {
+            Err(Error::TooManyRequests) => {
+                state.factor = (state.factor / 2.0).max(MIN_FACTOR);
+                state.tokens = state.tokens.min(0.0);
+                state.paused_until = Some(Instant::now() + PAUSE);
+            }
+            Ok(_) => state.factor = (state.factor + RECOVERY_STEP).min(1.0),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Sends a request through the limiter, if there is one.
+pub(crate) async fn send<T, E>(
+    limiter: Option<&ClientLimiter>,
+    priority: Priority,
+    request: impl Future<Output = Result<T, crate::openapi::apis::Error<E>>>,
+) -> Result<T, Error>
+where
+    Error: From<crate::openapi::apis::Error<E>>,
+{
+    let Some(limiter) = limiter else {
+        return request.await.map_err(Into::into);
+    };
+
+    limiter.acquire(priority).await;
+    let result = request.await.map_err(Into::into);
+    limiter.record(&result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a request of the given priority is let through without waiting.
+    async fn sends_now(limiter: &ClientLimiter, priority: Priority) -> bool {
+        tokio::time::timeout(Duration::from_millis(20), limiter.acquire(priority))
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn bulk_requests_leave_the_interactive_reserve() {
+        let limiter = ClientLimiter::new(
+            ClientRateLimit::new(1)
+                .with_burst(3)
+                .with_interactive_reserve(1),
+        );
+
+        assert!(sends_now(&limiter, Priority::Bulk).await);
+        assert!(sends_now(&limiter, Priority::Bulk).await);
+        assert!(!sends_now(&limiter, Priority::Bulk).await);
+        assert!(sends_now(&limiter, Priority::Interactive).await);
+        assert!(!sends_now(&limiter, Priority::Interactive).await);
+    }
+
+    #[tokio::test]
+    async fn tokens_refill_at_the_configured_rate() {
+        let limiter = ClientLimiter::new(ClientRateLimit::new(50).with_burst(1));
+
+        assert!(sends_now(&limiter, Priority::Interactive).await);
+        let started_at = Instant::now();
+        limiter.acquire(Priority::Interactive).await;
+        assert!(started_at.elapsed() >= Duration::from_millis(15));
+    }
+
+    #[tokio::test]
+    async fn too_many_requests_pauses_and_slows_down() {
+        let limiter = ClientLimiter::new(ClientRateLimit::new(100));
+
+        limiter.record::<()>(&Err(Error::TooManyRequests));
+        assert!(!sends_now(&limiter, Priority::Interactive).await);
+        assert_eq!(limiter.state.lock().unwrap().factor, 0.5);
+
+        limiter.record(&Ok(()));
+        limiter.record::<()>(&Err(Error::InternalServerError));
+        let state = limiter.state.lock().unwrap();
+        assert_eq!(state.factor, 0.5 + RECOVERY_STEP);
+    }
+
+    #[test]
+    fn the_rate_never_drops_below_the_minimum() {
+        let limiter = ClientLimiter::new(ClientRateLimit::new(100));
+        for _ in 0..10 {
+            limiter.record::<()>(&Err(Error::TooManyRequests));
+        }
+        assert_eq!(limiter.state.lock().unwrap().factor, MIN_FACTOR);
+    }
+}
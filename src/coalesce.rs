@@ -0,0 +1,279 @@
+//! Coalescing of concurrent identical reads.
+//!
+//! A page load often looks up the same user from several tasks at once, and each lookup
+//! resolves the external ID and fetches the user separately. With request coalescing enabled,
+//! `User` lets only one of a set of identical concurrent reads reach Passage; the others wait
+//! for it and receive a copy of its result. Coalesced reads are the external ID lookup, the
+//! user fetch and the device list. Writes are never coalesced.
+//!
+//! If the request being waited on is cancelled, one of the waiting callers sends it instead.
+//! Callers sharing a failed request get a copy of its error. `reqwest` errors can't be copied,
+//! so they are reported as `Error::Transient` with the same message when they are transient
+//! and as `Error::Other` otherwise; `serde_json` errors are reported as `Error::Other`.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::PassageFlex;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_request_coalescing();
+//!
+//! let (user, devices) = futures::join!(
+//!     passage_flex.user.get(external_id.clone()),
+//!     passage_flex.user.list_devices(external_id.clone()),
+//! );
+//!
+//! let stats = passage_flex.user.coalescing_stats().unwrap();
+//! println!("{} of {} reads were coalesced", stats.coalesced, stats.coalesced + stats.sent);
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use futures::channel::oneshot;
+
+use crate::openapi::models::{UserInfo, WebAuthnDevices};
+use crate::Error;
+
+/// How many reads were sent to Passage and how many were served by another caller's request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CoalescingStats {
+    /// Reads sent to Passage.
+    pub sent: u64,
+    /// Reads that shared another caller's in-flight request instead of sending their own.
+    pub coalesced: u64,
+}
+
+type Waiters<T> = Vec<oneshot::Sender<Result<T, Error>>>;
+
+/// Deduplicates concurrent requests with the same key.
+struct SingleFlight<T> {
+    in_flight: Mutex<HashMap<String, Waiters<T>>>,
+}
+
+impl<T: Clone> SingleFlight<T> {
+    fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn run(
+        &self,
+        key: &str,
+        stats: &Stats,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        loop {
+            let receiver = {
+                let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+                match in_flight.get_mut(key) {
+                    Some(waiters) => {
+                        let (sender, receiver) = oneshot::channel();
+                        waiters.push(sender);
+                        receiver
+                    }
+                    None => {
+                        in_flight.insert(key.to_string(), Vec::new());
+                        break;
+                    }
+                }
+            };
+
+            // The sender is dropped without a result if the request was cancelled, in which
+            // case this caller tries to send it itself.
+            if let Ok(result) = receiver.await {
+                stats.coalesced.fetch_add(1, Ordering::Relaxed);
+                return result;
+            }
+        }
+
+        let flight = Flight {
+            single_flight: self,
+            key,
+        };
+        stats.sent.fetch_add(1, Ordering::Relaxed);
+        let result = request.await;
+        for waiter in flight.finish() {
+            let _ = waiter.send(match &result {
+                Ok(value) => Ok(value.clone()),
+                Err(e) => Err(e.duplicate()),
+            });
+        }
+        result
+    }
+}
+
+/// The in-flight entry of the caller sending a request. Dropping it before the request
+/// finishes removes the entry, so waiting callers don't wait forever.
+struct Flight<'a, T> {
+    single_flight: &'a SingleFlight<T>,
+    key: &'a str,
+}
+
+impl<T> Flight<'_, T> {
+    fn take(&self) -> Waiters<T> {
+        let mut in_flight = self
+            .single_flight
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        in_flight.remove(self.key).unwrap_or_default()
+    }
+
+    fn finish(self) -> Waiters<T> {
+        let waiters = self.take();
+        std::mem::forget(self);
+        waiters
+    }
+}
+
+impl<T> Drop for Flight<'_, T> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    sent: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+/// The single-flight groups for each kind of coalesced read.
+pub(crate) struct Coalescing {
+    ids: SingleFlight<String>,
+    users: SingleFlight<UserInfo>,
+    devices: SingleFlight<Vec<WebAuthnDevices>>,
+    stats: Stats,
+}
+
+impl Coalescing {
+    pub(crate) fn new() -> Self {
+        Self {
+            ids: SingleFlight::new(),
+            users: SingleFlight::new(),
+            devices: SingleFlight::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CoalescingStats {
+        CoalescingStats {
+            sent: self.stats.sent.load(Ordering::Relaxed),
+            coalesced: self.stats.coalesced.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) async fn id(
+        &self,
+        external_id: &str,
+        request: impl Future<Output = Result<String, Error>>,
+    ) -> Result<String, Error> {
+        self.ids.run(external_id, &self.stats, request).await
+    }
+
+    pub(crate) async fn user(
+        &self,
+        user_id: &str,
+        request: impl Future<Output = Result<UserInfo, Error>>,
+    ) -> Result<UserInfo, Error> {
+        self.users.run(user_id, &self.stats, request).await
+    }
+
+    pub(crate) async fn devices(
+        &self,
+        user_id: &str,
+        request: impl Future<Output = Result<Vec<WebAuthnDevices>, Error>>,
+    ) -> Result<Vec<WebAuthnDevices>, Error> {
+        self.devices.run(user_id, &self.stats, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_reads_share_one_request() {
+        let coalescing = Coalescing::new();
+        let sent = &AtomicUsize::new(0);
+        let read = |key: &'static str| {
+            coalescing.id(key, async move {
+                sent.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok(format!("id-{}", key))
+            })
+        };
+
+        let (a, b, c) = tokio::join!(read("user-1"), read("user-1"), read("user-2"));
+        assert_eq!(a.unwrap(), "id-user-1");
+        assert_eq!(b.unwrap(), "id-user-1");
+        assert_eq!(c.unwrap(), "id-user-2");
+        assert_eq!(sent.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            coalescing.stats(),
+            CoalescingStats {
+                sent: 2,
+                coalesced: 1
+            }
+        );
+
+        read("user-1").await.unwrap();
+        assert_eq!(sent.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn waiters_get_a_copy_of_the_error() {
+        let coalescing = Coalescing::new();
+        let error = reqwest::Client::new()
+            .get("http://127.0.0.1:1")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(error.is_connect());
+
+        let mut error = Some(Error::Reqwest(error));
+        let leader = coalescing.id("user-1", async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Err(error.take().unwrap())
+        });
+        let waiter = coalescing.id("user-1", async { unreachable!() });
+        let (leader, waiter) = tokio::join!(leader, waiter);
+
+        assert!(matches!(leader, Err(Error::Reqwest(_))));
+        let waiter = waiter.unwrap_err();
+        assert!(matches!(waiter, Error::Transient(_)));
+        assert!(waiter.is_transient());
+    }
+
+    #[tokio::test]
+    async fn a_waiter_sends_the_request_when_the_leader_is_cancelled() {
+        let coalescing = Coalescing::new();
+
+        let mut leader = Box::pin(coalescing.id("user-1", futures::future::pending()));
+        assert!(futures::poll!(&mut leader).is_pending());
+        let mut waiter = Box::pin(coalescing.id("user-1", async { Ok("id-user-1".to_string()) }));
+        assert!(futures::poll!(&mut waiter).is_pending());
+
+        drop(leader);
+        assert_eq!(waiter.await.unwrap(), "id-user-1");
+        assert_eq!(
+            coalescing.stats(),
+            CoalescingStats {
+                sent: 2,
+                coalesced: 0
+            }
+        );
+        assert!(coalescing.ids.in_flight.lock().unwrap().is_empty());
+    }
+}
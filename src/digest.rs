@@ -0,0 +1,26 @@
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+/// Hex-encoded SHA-256 digest of a value, used wherever a secret or identifier is kept
+/// without storing it in plain text.
+pub(crate) fn sha256_hex(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The shortest HMAC-SHA256 secret accepted, matching the size of the SHA-256 output.
+pub(crate) const MIN_HS256_SECRET_LEN: usize = 32;
+
+/// Rejects HMAC-SHA256 secrets short enough to be guessed.
+pub(crate) fn check_hs256_secret(secret: &[u8]) -> Result<(), Error> {
+    if secret.len() < MIN_HS256_SECRET_LEN {
+        return Err(Error::InvalidArgument(format!(
+            "HS256 secrets must be at least {} bytes",
+            MIN_HS256_SECRET_LEN
+        )));
+    }
+    Ok(())
+}
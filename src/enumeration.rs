@@ -0,0 +1,200 @@
+//! Protection against user enumeration through transaction creation.
+//!
+//! `Auth::create_authenticate_transaction` fails with `Error::UserNotFound` or
+//! `Error::UserHasNoPasskeys` for unknown users, and a login endpoint that relays the
+//! difference lets anyone check which accounts exist. A transaction for a real user also lists
+//! their credentials, so even a decoy transaction would be told apart by the ceremony it
+//! starts.
+//!
+//! With enumeration protection enabled, every authenticate transaction is usernameless: the
+//! SDK looks the requested user up, then returns a discoverable transaction whatever the
+//! answer, so the browser shows the same passkey picker to everyone. Users must therefore sign
+//! in with discoverable passkeys, and protection relies on the same undocumented Passage
+//! behavior as `Auth::create_discoverable_authenticate_transaction`. The lookup only reads,
+//! and is served from the cache when one is configured. Only the usernameless transaction is
+//! created, so each call takes one transaction rate limit token.
+//!
+//! Every call is padded to a minimum response time. This is a best-effort floor: a call that
+//! takes longer, such as when Passage is slow, still returns late, and looking up a known user
+//! takes longer than an unknown one on a cache miss. Set the minimum above the p99 latency of
+//! a lookup and a transaction request together for the padding to hide the difference.
+//!
+//! The real outcome is only reported through the `AuditReceiver`, which buffers up to
+//! `AUDIT_CAPACITY` events. Since unauthenticated callers trigger them, events that arrive
+//! while the buffer is full are dropped rather than queued, and counted by
+//! `AuditReceiver::dropped`. Unknown users don't count towards throttle lockouts, so lockouts
+//! don't reveal them either.
+//!
+//! The returned transaction carries the requested external ID, so verify its nonce with
+//! `Auth::verify_tracked_nonce`, which rejects a nonce that verifies to a different user. A
+//! plain `Auth::verify_nonce` would accept whoever completed the ceremony.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use futures::StreamExt;
+//! use passage_flex::enumeration::EnumerationProtection;
+//! use passage_flex::PassageFlex;
+//! use std::time::Duration;
+//!
+//! let (protection, mut audit) = EnumerationProtection::new(Duration::from_millis(500));
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_enumeration_protection(protection);
+//!
+//! tokio::spawn(async move {
+//!     while let Some(event) = audit.next().await {
+//!         if event.decoy {
+//!             // record the attempt for an unknown user or a user without passkeys
+//!         }
+//!     }
+//! });
+//! ```
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+
+/// The number of audit events buffered while the receiver isn't keeping up.
+pub const AUDIT_CAPACITY: usize = 1024;
+
+/// What actually happened when an authenticate transaction was requested.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// The user exists and has passkeys.
+    Found,
+    /// No user with the external ID exists.
+    UserNotFound,
+    /// The user exists but has no passkeys.
+    UserHasNoPasskeys,
+}
+
+/// The real outcome of an authenticate transaction request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub external_id: String,
+    /// The ID of the transaction returned to the caller.
+    pub transaction_id: String,
+    pub outcome: AuditOutcome,
+    /// Whether the returned transaction was a decoy, which can't authenticate the user.
+    pub decoy: bool,
+    pub at: DateTime<Utc>,
+}
+
+/// Receives an `AuditEvent` for every authenticate transaction created while enumeration
+/// protection is enabled, as a `Stream`.
+#[derive(Debug)]
+pub struct AuditReceiver {
+    receiver: Receiver<AuditEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AuditReceiver {
+    /// The number of events dropped because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for AuditReceiver {
+    type Item = AuditEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<AuditEvent>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+/// Settings for enumeration protection.
+#[derive(Clone, Debug)]
+pub struct EnumerationProtection {
+    pub(crate) min_response_time: Duration,
+    // Shared by every clone, since each sender may add one event beyond the buffer.
+    audit: Arc<Mutex<Sender<AuditEvent>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EnumerationProtection {
+    /// Creates the settings and the receiving end of the audit channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `min_response_time` - The least time every authenticate transaction request takes.
+    ///   Set it above the p99 latency of a user lookup and a transaction request to Passage.
+    pub fn new(min_response_time: Duration) -> (Self, AuditReceiver) {
+        // The sender adds one event beyond the buffer before it reports being full.
+        let (audit, receiver) = mpsc::channel(AUDIT_CAPACITY - 1);
+        let dropped = Arc::new(AtomicU64::new(0));
+        (
+            Self {
+                min_response_time,
+                audit: Arc::new(Mutex::new(audit)),
+                dropped: dropped.clone(),
+            },
+            AuditReceiver { receiver, dropped },
+        )
+    }
+
+    /// Reports an outcome. Events are dropped if the receiver is gone, and dropped and counted
+    /// if its buffer is full.
+    pub(crate) fn report(&self, external_id: &str, transaction_id: &str, outcome: AuditOutcome) {
+        let event = AuditEvent {
+            external_id: external_id.to_string(),
+            transaction_id: transaction_id.to_string(),
+            outcome,
+            decoy: outcome != AuditOutcome::Found,
+            at: Utc::now(),
+        };
+        let mut audit = self.audit.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = audit.try_send(event) {
+            if e.is_full() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn events_beyond_the_buffer_are_dropped_and_counted() {
+        let (protection, mut audit) = EnumerationProtection::new(Duration::ZERO);
+        let other = protection.clone();
+        for i in 0..AUDIT_CAPACITY + 10 {
+            let protection = if i % 2 == 0 { &protection } else { &other };
+            protection.report(&format!("user-{i}"), "txn", AuditOutcome::UserNotFound);
+        }
+        assert_eq!(audit.dropped(), 10);
+
+        for i in 0..AUDIT_CAPACITY {
+            assert_eq!(audit.next().await.unwrap().external_id, format!("user-{i}"));
+        }
+        assert!(futures::poll!(audit.next()).is_pending());
+
+        protection.report("user-late", "txn", AuditOutcome::Found);
+        let event = audit.next().await.unwrap();
+        assert_eq!(event.external_id, "user-late");
+        assert!(!event.decoy);
+        assert_eq!(audit.dropped(), 10);
+    }
+
+    #[test]
+    fn events_are_discarded_once_the_receiver_is_gone() {
+        let (protection, audit) = EnumerationProtection::new(Duration::ZERO);
+        drop(audit);
+        protection.report("user-1", "txn", AuditOutcome::Found);
+    }
+}
@@ -1,12 +1,81 @@
+use crate::apis;
 use crate::openapi::apis::{authenticate_api, transactions_api, user_devices_api, users_api};
 use crate::Error;
 
+impl Error {
+    /// Whether the error is likely temporary, such that retrying the same request may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Reqwest(e) => e.is_timeout() || e.is_connect(),
+            Error::InternalServerError
+            | Error::TooManyRequests
+            | Error::CircuitOpen
+            | Error::Transient(_) => true,
+            _ => false,
+        }
+    }
+
+    /// A copy of the error for callers sharing a coalesced request. Errors from `reqwest` and
+    /// `serde_json` can't be copied and become `Error::Transient` or `Error::Other` with the
+    /// same message, depending on whether they are transient.
+    pub(crate) fn duplicate(&self) -> Error {
+        match self {
+            Error::Reqwest(e) if self.is_transient() => Error::Transient(e.to_string()),
+            Error::Reqwest(e) => Error::Other(e.to_string()),
+            Error::Serde(e) => Error::Other(e.to_string()),
+            Error::Io(e) => Error::Io(std::io::Error::new(e.kind(), e.to_string())),
+            Error::InvalidRequest(e) => Error::InvalidRequest(e.clone()),
+            Error::InvalidAccessToken => Error::InvalidAccessToken,
+            Error::InvalidNonce => Error::InvalidNonce,
+            Error::OperationNotAllowed => Error::OperationNotAllowed,
+            Error::DeviceNotFound => Error::DeviceNotFound,
+            Error::UserNotFound => Error::UserNotFound,
+            Error::UserHasNoPasskeys => Error::UserHasNoPasskeys,
+            Error::InternalServerError => Error::InternalServerError,
+            Error::Other(e) => Error::Other(e.clone()),
+            Error::InvalidArgument(e) => Error::InvalidArgument(e.clone()),
+            Error::NonceReplayed => Error::NonceReplayed,
+            Error::Store(e) => Error::Store(e.clone()),
+            Error::TransactionNotFound => Error::TransactionNotFound,
+            Error::TransactionExpired => Error::TransactionExpired,
+            Error::TransactionMismatch => Error::TransactionMismatch,
+            Error::InvalidSessionToken(e) => Error::InvalidSessionToken(e.clone()),
+            Error::SessionNotFound => Error::SessionNotFound,
+            Error::SessionExpired => Error::SessionExpired,
+            Error::SessionRevoked => Error::SessionRevoked,
+            Error::InvalidStepUp(e) => Error::InvalidStepUp(e.clone()),
+            Error::TemporarilyLocked { retry_after } => Error::TemporarilyLocked {
+                retry_after: *retry_after,
+            },
+            Error::RateLimited { retry_after } => Error::RateLimited {
+                retry_after: *retry_after,
+            },
+            Error::TooManyRequests => Error::TooManyRequests,
+            Error::CircuitOpen => Error::CircuitOpen,
+            Error::Middleware(e) => Error::Middleware(e.clone()),
+            Error::DeadlineExceeded => Error::DeadlineExceeded,
+            Error::Transient(e) => Error::Transient(e.clone()),
+        }
+    }
+}
+
 // This function converts an openapi error into a crate error
 fn convert_error<Src>(error: crate::openapi::apis::Error<Src>, map_fn: fn(Src) -> Error) -> Error {
     match error {
         crate::openapi::apis::Error::Reqwest(e) => Error::Reqwest(e), // Forward the reqwest error directly
+        crate::openapi::apis::Error::ReqwestMiddleware(reqwest_middleware::Error::Reqwest(e)) => {
+            Error::Reqwest(e)
+        }
+        crate::openapi::apis::Error::ReqwestMiddleware(reqwest_middleware::Error::Middleware(
+            e,
+        )) => Error::Middleware(e.to_string()),
         crate::openapi::apis::Error::Serde(e) => Error::Serde(e), // Forward the serde error directly
         crate::openapi::apis::Error::Io(e) => Error::Io(e),       // Forward the I/O error directly
+        crate::openapi::apis::Error::ResponseError(response)
+            if response.status == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+        {
+            Error::TooManyRequests
+        }
         crate::openapi::apis::Error::ResponseError(response) => match response.entity {
             Some(entity) => map_fn(entity),
             None => Error::Other(response.content),
@@ -80,6 +149,51 @@ impl From<crate::openapi::apis::Error<users_api::ListPaginatedUsersError>> for E
     }
 }
 
+impl From<crate::openapi::apis::Error<apis::ActivateUserError>> for Error {
+    fn from(e: crate::openapi::apis::Error<apis::ActivateUserError>) -> Self {
+        convert_error(e, |e| match e {
+            apis::ActivateUserError::Status401(model) => model.into(),
+            apis::ActivateUserError::Status404(model) => model.into(),
+            apis::ActivateUserError::Status500(model) => model.into(),
+            apis::ActivateUserError::UnknownValue(v) => Error::Other(v.to_string()),
+        })
+    }
+}
+
+impl From<crate::openapi::apis::Error<apis::CreateUserError>> for Error {
+    fn from(e: crate::openapi::apis::Error<apis::CreateUserError>) -> Self {
+        convert_error(e, |e| match e {
+            apis::CreateUserError::Status400(model) => model.into(),
+            apis::CreateUserError::Status401(model) => model.into(),
+            apis::CreateUserError::Status404(model) => model.into(),
+            apis::CreateUserError::Status500(model) => model.into(),
+            apis::CreateUserError::UnknownValue(v) => Error::Other(v.to_string()),
+        })
+    }
+}
+
+impl From<crate::openapi::apis::Error<apis::DeactivateUserError>> for Error {
+    fn from(e: crate::openapi::apis::Error<apis::DeactivateUserError>) -> Self {
+        convert_error(e, |e| match e {
+            apis::DeactivateUserError::Status401(model) => model.into(),
+            apis::DeactivateUserError::Status404(model) => model.into(),
+            apis::DeactivateUserError::Status500(model) => model.into(),
+            apis::DeactivateUserError::UnknownValue(v) => Error::Other(v.to_string()),
+        })
+    }
+}
+
+impl From<crate::openapi::apis::Error<apis::DeleteUserError>> for Error {
+    fn from(e: crate::openapi::apis::Error<apis::DeleteUserError>) -> Self {
+        convert_error(e, |e| match e {
+            apis::DeleteUserError::Status401(model) => model.into(),
+            apis::DeleteUserError::Status404(model) => model.into(),
+            apis::DeleteUserError::Status500(model) => model.into(),
+            apis::DeleteUserError::UnknownValue(v) => Error::Other(v.to_string()),
+        })
+    }
+}
+
 impl From<crate::openapi::apis::Error<users_api::GetUserError>> for Error {
     fn from(e: crate::openapi::apis::Error<users_api::GetUserError>) -> Self {
         convert_error(e, |e| match e {
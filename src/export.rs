@@ -0,0 +1,513 @@
+//! Streaming export of an app's users and their passkey devices.
+//!
+//! Users are fetched one page at a time and written to any `AsyncWrite` as JSON Lines or
+//! CSV, so memory use stays bounded by the page size regardless of how many users the app
+//! has. After every page is flushed the export records a [`Checkpoint`] which can be used
+//! to resume an interrupted export.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::export::{Column, ExportOptions, Format, UserExport};
+//! use passage_flex::PassageFlex;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! );
+//!
+//! let mut file = tokio::fs::File::create("users.csv").await.unwrap();
+//! let mut export = UserExport::new(
+//!     &passage_flex.user,
+//!     ExportOptions {
+//!         format: Format::Csv,
+//!         columns: vec![Column::ExternalId, Column::Status, Column::WebauthnDevices],
+//!         ..Default::default()
+//!     },
+//! );
+//!
+//! if let Err(err) = export.write_to(&mut file).await {
+//!     // persist export.checkpoint() and pass it as `resume_from` to continue later
+//! }
+//! ```
+
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::openapi::models::ListPaginatedUsersItem;
+use crate::user::User;
+use crate::Error;
+
+/// Number of device list requests allowed in flight at once.
+const CONCURRENCY: usize = 8;
+
+/// The output format of an export.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Format {
+    /// One JSON object per line.
+    #[default]
+    Jsonl,
+    /// Comma-separated values with a header row.
+    Csv,
+}
+
+/// A column that can be included in an export.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Column {
+    Id,
+    ExternalId,
+    Email,
+    EmailVerified,
+    Phone,
+    PhoneVerified,
+    Status,
+    CreatedAt,
+    UpdatedAt,
+    LastLoginAt,
+    LoginCount,
+    UserMetadata,
+    /// The user's passkey devices. Requires one extra request per user.
+    WebauthnDevices,
+}
+
+impl Column {
+    /// Every user column, excluding `WebauthnDevices`.
+    pub const USER: [Column; 12] = [
+        Column::Id,
+        Column::ExternalId,
+        Column::Email,
+        Column::EmailVerified,
+        Column::Phone,
+        Column::PhoneVerified,
+        Column::Status,
+        Column::CreatedAt,
+        Column::UpdatedAt,
+        Column::LastLoginAt,
+        Column::LoginCount,
+        Column::UserMetadata,
+    ];
+
+    /// The field name used for this column in JSON objects and CSV headers.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::ExternalId => "external_id",
+            Column::Email => "email",
+            Column::EmailVerified => "email_verified",
+            Column::Phone => "phone",
+            Column::PhoneVerified => "phone_verified",
+            Column::Status => "status",
+            Column::CreatedAt => "created_at",
+            Column::UpdatedAt => "updated_at",
+            Column::LastLoginAt => "last_login_at",
+            Column::LoginCount => "login_count",
+            Column::UserMetadata => "user_metadata",
+            Column::WebauthnDevices => "webauthn_devices",
+        }
+    }
+}
+
+/// The position of an export within the app's users.
+///
+/// `page` is the next page to be exported, `created_before` is the time anchor (Unix
+/// timestamp) that pins every page to the same snapshot of users, and `header_written` is
+/// whether the CSV header has already been written.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub page: i32,
+    pub created_before: Option<i64>,
+    #[serde(default)]
+    pub header_written: bool,
+}
+
+impl Default for Checkpoint {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            created_before: None,
+            header_written: false,
+        }
+    }
+}
+
+/// Options controlling what an export writes.
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    pub format: Format,
+    /// The columns to write, in order.
+    pub columns: Vec<Column>,
+    /// The number of users fetched per page, which bounds memory use.
+    pub page_limit: i32,
+    /// Continue a previous export from this checkpoint. The CSV header is only written if the
+    /// checkpoint records that it wasn't yet.
+    pub resume_from: Option<Checkpoint>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::default(),
+            columns: Column::USER.to_vec(),
+            page_limit: 100,
+            resume_from: None,
+        }
+    }
+}
+
+/// A resumable, streaming export of an app's users.
+pub struct UserExport<'a> {
+    user: &'a User,
+    options: ExportOptions,
+    checkpoint: Checkpoint,
+    exported: u64,
+    done: bool,
+}
+
+impl<'a> UserExport<'a> {
+    /// Creates a new export of the users visible to `user`.
+    pub fn new(user: &'a User, options: ExportOptions) -> Self {
+        let checkpoint = options.resume_from.clone().unwrap_or_default();
+        Self {
+            user,
+            options,
+            checkpoint,
+            exported: 0,
+            done: false,
+        }
+    }
+
+    /// The position to resume from, or `None` once every user has been written.
+    pub fn checkpoint(&self) -> Option<&Checkpoint> {
+        (!self.done).then_some(&self.checkpoint)
+    }
+
+    /// The number of users written so far by this export.
+    pub fn exported(&self) -> u64 {
+        self.exported
+    }
+
+    /// Writes every remaining user to `writer`, flushing after each page.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The destination of the export.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of users written by this call or an `Error`. On error,
+    /// `checkpoint()` points at the first page that was not fully written.
+    pub async fn write_to<W>(&mut self, writer: &mut W) -> Result<u64, Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        if self.options.columns.is_empty() {
+            return Err(Error::InvalidArgument(
+                "at least one column is required".to_string(),
+            ));
+        }
+
+        if self.options.page_limit < 1 {
+            return Err(Error::InvalidArgument(
+                "page_limit must be positive".to_string(),
+            ));
+        }
+
+        let started_with = self.exported;
+        if self.options.format == Format::Csv && !self.checkpoint.header_written {
+            let header: Vec<&str> = self.options.columns.iter().map(Column::name).collect();
+            writer
+                .write_all(&csv_record(&header)?)
+                .await
+                .map_err(Error::Io)?;
+            writer.flush().await.map_err(Error::Io)?;
+            self.checkpoint.header_written = true;
+        }
+
+        while !self.done {
+            let response = self
+                .user
+                .list_page(
+                    self.checkpoint.page,
+                    self.options.page_limit,
+                    self.checkpoint.created_before,
+                )
+                .await?;
+
+            let fetched = response.users.len();
+            let bytes = self.encode_page(response.users).await?;
+            writer.write_all(&bytes).await.map_err(Error::Io)?;
+            writer.flush().await.map_err(Error::Io)?;
+
+            self.exported += fetched as u64;
+            self.checkpoint
+                .created_before
+                .get_or_insert(response.created_before);
+            self.done = fetched < self.options.page_limit as usize
+                || i64::from(self.checkpoint.page) * i64::from(self.options.page_limit)
+                    >= response.total_users;
+            self.checkpoint.page += 1;
+        }
+
+        Ok(self.exported - started_with)
+    }
+
+    async fn encode_page(&self, users: Vec<ListPaginatedUsersItem>) -> Result<Vec<u8>, Error> {
+        let with_devices = self.options.columns.contains(&Column::WebauthnDevices);
+        let rows: Vec<Row> = futures::stream::iter(users)
+            .map(|item| async move {
+                let devices = if with_devices {
+                    Some(self.user.list_devices_by_id(&item.id).await?)
+                } else {
+                    None
+                };
+                self.row(item, devices)
+            })
+            .buffered(CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let mut bytes = Vec::new();
+        for row in rows {
+            match self.options.format {
+                Format::Jsonl => {
+                    serde_json::to_writer(&mut bytes, &row).map_err(Error::Serde)?;
+                    bytes.push(b'\n');
+                }
+                Format::Csv => {
+                    let fields: Vec<String> = row
+                        .0
+                        .into_iter()
+                        .map(|(_, value)| match value {
+                            serde_json::Value::Null => String::new(),
+                            serde_json::Value::String(s) => s,
+                            other => other.to_string(),
+                        })
+                        .collect();
+                    bytes.extend(csv_record(&fields)?);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn row(
+        &self,
+        item: ListPaginatedUsersItem,
+        devices: Option<Vec<crate::openapi::models::WebAuthnDevices>>,
+    ) -> Result<Row, Error> {
+        let mut fields = match serde_json::to_value(item).map_err(Error::Serde)? {
+            serde_json::Value::Object(fields) => fields,
+            _ => serde_json::Map::new(),
+        };
+        if let Some(devices) = devices {
+            fields.insert(
+                Column::WebauthnDevices.name().to_string(),
+                serde_json::to_value(devices).map_err(Error::Serde)?,
+            );
+        }
+
+        Ok(Row(self
+            .options
+            .columns
+            .iter()
+            .map(|column| {
+                let value = fields
+                    .remove(column.name())
+                    .unwrap_or(serde_json::Value::Null);
+                (column.name(), value)
+            })
+            .collect()))
+    }
+}
+
+/// A single exported user, with fields kept in column order.
+struct Row(Vec<(&'static str, serde_json::Value)>);
+
+impl Serialize for Row {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(name, value)| (name, value)))
+    }
+}
+
+fn csv_record<T: AsRef<[u8]>>(fields: &[T]) -> Result<Vec<u8>, Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(fields)
+        .map_err(|e| Error::Other(e.to_string()))?;
+    writer.into_inner().map_err(|e| Error::Other(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_server::{users_page, TestServer};
+
+    const SERVER_ERROR: &str = r#"{"code":"internal_server_error","error":"boom"}"#;
+
+    fn csv_options(page_limit: i32) -> ExportOptions {
+        ExportOptions {
+            format: Format::Csv,
+            columns: vec![Column::ExternalId, Column::Status],
+            page_limit,
+            resume_from: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_csv_header_and_pages() {
+        let server = TestServer::start(|request| async move {
+            if request.path.contains("page=1") {
+                (200, users_page(&["a", "b"], 3))
+            } else {
+                (200, users_page(&["c"], 3))
+            }
+        })
+        .await;
+        let user = User::new(server.configuration());
+
+        let mut output = Vec::new();
+        let mut export = UserExport::new(&user, csv_options(2));
+        assert_eq!(export.write_to(&mut output).await.unwrap(), 3);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "external_id,status\na,active\nb,active\nc,active\n"
+        );
+        assert_eq!(export.checkpoint(), None);
+        assert_eq!(export.exported(), 3);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].path.contains("page=2"));
+        assert!(requests[1].path.contains("created_before=1700000000"));
+    }
+
+    #[tokio::test]
+    async fn resume_writes_header_only_once() {
+        let fail = Arc::new(AtomicBool::new(true));
+        let server = TestServer::start({
+            let fail = fail.clone();
+            move |_| {
+                let fail = fail.load(Ordering::SeqCst);
+                async move {
+                    if fail {
+                        (500, SERVER_ERROR.to_string())
+                    } else {
+                        (200, users_page(&["a"], 1))
+                    }
+                }
+            }
+        })
+        .await;
+        let user = User::new(server.configuration());
+
+        let mut output = Vec::new();
+        let mut export = UserExport::new(&user, csv_options(10));
+        assert!(export.write_to(&mut output).await.is_err());
+        let checkpoint = export.checkpoint().cloned().unwrap();
+        assert_eq!(checkpoint.page, 1);
+        assert!(checkpoint.header_written);
+
+        fail.store(false, Ordering::SeqCst);
+        let mut resumed = UserExport::new(
+            &user,
+            ExportOptions {
+                resume_from: Some(checkpoint),
+                ..csv_options(10)
+            },
+        );
+        resumed.write_to(&mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "external_id,status\na,active\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn resume_writes_header_that_was_never_written() {
+        let server = TestServer::start(|_| async { (200, users_page(&["a"], 1)) }).await;
+        let user = User::new(server.configuration());
+
+        let mut output = Vec::new();
+        let mut export = UserExport::new(
+            &user,
+            ExportOptions {
+                resume_from: Some(Checkpoint {
+                    page: 1,
+                    created_before: Some(1_700_000_000),
+                    header_written: false,
+                }),
+                ..csv_options(10)
+            },
+        );
+        export.write_to(&mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "external_id,status\na,active\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_jsonl_in_column_order() {
+        let server = TestServer::start(|_| async { (200, users_page(&["a"], 1)) }).await;
+        let user = User::new(server.configuration());
+
+        let mut output = Vec::new();
+        let mut export = UserExport::new(
+            &user,
+            ExportOptions {
+                columns: vec![Column::Status, Column::ExternalId, Column::UserMetadata],
+                ..Default::default()
+            },
+        );
+        export.write_to(&mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"status\":\"active\",\"external_id\":\"a\",\"user_metadata\":null}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_options() {
+        let user = User::new(crate::openapi::apis::configuration::Configuration::default());
+        let mut output = Vec::new();
+
+        let mut export = UserExport::new(
+            &user,
+            ExportOptions {
+                columns: vec![],
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            export.write_to(&mut output).await,
+            Err(Error::InvalidArgument(_))
+        ));
+
+        let mut export = UserExport::new(&user, csv_options(0));
+        assert!(matches!(
+            export.write_to(&mut output).await,
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn checkpoints_without_header_field_deserialize() {
+        let checkpoint: Checkpoint =
+            serde_json::from_str(r#"{"page":3,"created_before":1700000000}"#).unwrap();
+        assert_eq!(checkpoint.page, 3);
+        assert!(!checkpoint.header_written);
+    }
+
+    #[test]
+    fn csv_record_quotes_fields() {
+        assert_eq!(
+            csv_record(&["a,b", "c\"d", "e"]).unwrap(),
+            b"\"a,b\",\"c\"\"d\",e\n".to_vec()
+        );
+    }
+}
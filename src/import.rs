@@ -0,0 +1,658 @@
+//! Bulk import of users from CSV or JSON Lines.
+//!
+//! Each input row carries an `external_id` and optional `user_metadata` object. Rows are
+//! validated, checked against the app's existing users, and created with bounded
+//! concurrency. Requests are retried only by the client's retry policy, or the one set in
+//! `ImportOptions::retry`. Each row's create is sent with an idempotency key derived from its
+//! external ID, so that it is retried too. If the response to a create is lost, the row is
+//! checked again and reported as created when the user now exists. The outcome of every row
+//! is written to a JSON Lines result log in input order. A row that can't be decoded, such as
+//! a line that isn't valid UTF-8, is reported as invalid without stopping the import.
+//!
+//! CSV input must have a header row containing an `external_id` column and may contain a
+//! `user_metadata` column holding a JSON object.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::export::Format;
+//! use passage_flex::import::{self, ImportOptions};
+//! use passage_flex::PassageFlex;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! );
+//!
+//! let input = tokio::io::BufReader::new(tokio::fs::File::open("users.csv").await.unwrap());
+//! let mut log = tokio::fs::File::create("import-results.jsonl").await.unwrap();
+//! let summary = import::import_users(
+//!     &passage_flex.user,
+//!     input,
+//!     &mut log,
+//!     ImportOptions {
+//!         format: Format::Csv,
+//!         ..Default::default()
+//!     },
+//! )
+//! .await
+//! .unwrap();
+//! println!("created {} users", summary.created);
+//! ```
+
+use std::collections::HashSet;
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::call_options::{CallOptions, Retry};
+use crate::digest;
+pub use crate::export::Format;
+use crate::user::User;
+use crate::Error;
+
+/// The maximum length of an external ID, in bytes.
+const MAX_EXTERNAL_ID_LEN: usize = 255;
+
+/// Options controlling how an import runs.
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    pub format: Format,
+    /// The number of rows processed at once.
+    pub concurrency: usize,
+    /// Overrides the client's retry policy for the requests made for each row.
+    pub retry: Option<Retry>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::default(),
+            concurrency: 4,
+            retry: None,
+        }
+    }
+}
+
+/// A single user to be imported.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImportRow {
+    pub external_id: String,
+    #[serde(default)]
+    pub user_metadata: Option<serde_json::Value>,
+}
+
+/// What happened to an input row.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The user was created.
+    Created,
+    /// A user with the external ID already exists, or the ID appeared earlier in the input.
+    Skipped,
+    /// The row failed validation and no request was made.
+    Invalid,
+    /// The user could not be created.
+    Failed,
+}
+
+/// The result log entry for a single input row.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RowResult {
+    /// The line of the input on which the row starts.
+    pub line: usize,
+    pub external_id: Option<String>,
+    pub outcome: Outcome,
+    /// The Passage user ID of the created or existing user.
+    pub user_id: Option<String>,
+    pub error: Option<String>,
+}
+
+impl RowResult {
+    fn rejected(line: usize, external_id: Option<String>, outcome: Outcome, error: String) -> Self {
+        Self {
+            line,
+            external_id,
+            outcome,
+            user_id: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Totals of an import run.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub created: u64,
+    pub skipped: u64,
+    pub invalid: u64,
+    pub failed: u64,
+}
+
+/// Imports users read from `reader`, writing one `RowResult` per row to `log`.
+///
+/// # Arguments
+///
+/// * `user` - The `User` client used to look up and create users.
+/// * `reader` - The CSV or JSON Lines input.
+/// * `log` - The destination of the JSON Lines result log.
+/// * `options` - The input format, concurrency and retry policy.
+///
+/// # Returns
+///
+/// A `Result` containing the `ImportSummary` or an `Error` if the input or log could not be
+/// read or written. Failures of individual rows are reported in the log, not as an `Error`.
+pub async fn import_users<R, W>(
+    user: &User,
+    reader: R,
+    log: &mut W,
+    options: ImportOptions,
+) -> Result<ImportSummary, Error>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if options.concurrency == 0 {
+        return Err(Error::InvalidArgument(
+            "concurrency must be positive".to_string(),
+        ));
+    }
+
+    let rows = futures::stream::unfold(
+        (RowReader::new(reader, options.format), HashSet::new()),
+        |(mut reader, mut seen)| async move {
+            let next = match reader.next().await {
+                Ok(Some(Pending::Import { line, row }))
+                    if !seen.insert(row.external_id.clone()) =>
+                {
+                    Ok(Pending::Rejected(RowResult::rejected(
+                        line,
+                        Some(row.external_id),
+                        Outcome::Skipped,
+                        "duplicate external_id in input".to_string(),
+                    )))
+                }
+                Ok(Some(pending)) => Ok(pending),
+                Ok(None) => return None,
+                Err(e) => Err(e),
+            };
+            Some((next, (reader, seen)))
+        },
+    );
+
+    let options = &options;
+    let results = rows
+        .map(|pending| async move {
+            Ok::<_, Error>(match pending? {
+                Pending::Import { line, row } => import_row(user, line, row, options.retry).await,
+                Pending::Rejected(result) => result,
+            })
+        })
+        .buffered(options.concurrency);
+    let mut results = std::pin::pin!(results);
+
+    let mut summary = ImportSummary::default();
+    while let Some(result) = results.next().await {
+        let result = result?;
+        match result.outcome {
+            Outcome::Created => summary.created += 1,
+            Outcome::Skipped => summary.skipped += 1,
+            Outcome::Invalid => summary.invalid += 1,
+            Outcome::Failed => summary.failed += 1,
+        }
+
+        let mut bytes = serde_json::to_vec(&result).map_err(Error::Serde)?;
+        bytes.push(b'\n');
+        log.write_all(&bytes).await.map_err(Error::Io)?;
+    }
+    log.flush().await.map_err(Error::Io)?;
+
+    Ok(summary)
+}
+
+async fn import_row(user: &User, line: usize, row: ImportRow, retry: Option<Retry>) -> RowResult {
+    let import = async {
+        match user.get_id(row.external_id.clone()).await {
+            Ok(user_id) => Ok((Outcome::Skipped, user_id)),
+            Err(Error::UserNotFound) => {
+                let created = user
+                    .create(row.external_id.clone(), row.user_metadata.clone())
+                    .await;
+                match created {
+                    Ok(created) => Ok((Outcome::Created, created.id)),
+                    // The create may have taken effect even though its response was lost.
+                    Err(e @ Error::Reqwest(_)) => {
+                        match user.get_id(row.external_id.clone()).await {
+                            Ok(user_id) => Ok((Outcome::Created, user_id)),
+                            Err(_) => Err(e),
+                        }
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    };
+    let mut options = CallOptions::new().with_idempotency_key(idempotency_key(&row.external_id));
+    if let Some(retry) = retry {
+        options = options.with_retry(retry);
+    }
+    let imported = options.run(import).await;
+
+    match imported {
+        Ok((outcome, user_id)) => RowResult {
+            line,
+            external_id: Some(row.external_id),
+            outcome,
+            user_id: Some(user_id),
+            error: None,
+        },
+        Err(e) => RowResult::rejected(line, Some(row.external_id), Outcome::Failed, e.to_string()),
+    }
+}
+
+/// The idempotency key of a row's create, the same for every import of the external ID.
+fn idempotency_key(external_id: &str) -> String {
+    format!("import-{}", digest::sha256_hex(external_id))
+}
+
+/// Checks an input row against the external ID and metadata rules.
+fn validate(row: &ImportRow) -> Result<(), String> {
+    let external_id = &row.external_id;
+    if external_id.is_empty() {
+        return Err("external_id is required".to_string());
+    }
+    if external_id.len() > MAX_EXTERNAL_ID_LEN {
+        return Err(format!(
+            "external_id must be at most {} bytes",
+            MAX_EXTERNAL_ID_LEN
+        ));
+    }
+    if external_id.trim() != external_id {
+        return Err("external_id must not have leading or trailing whitespace".to_string());
+    }
+    if external_id.chars().any(char::is_control) {
+        return Err("external_id must not contain control characters".to_string());
+    }
+
+    match &row.user_metadata {
+        None | Some(serde_json::Value::Object(_)) => Ok(()),
+        Some(_) => Err("user_metadata must be a JSON object".to_string()),
+    }
+}
+
+/// An input row, either still to be imported or already resolved without any API calls.
+enum Pending {
+    Import { line: usize, row: ImportRow },
+    Rejected(RowResult),
+}
+
+/// Reads import rows one at a time from CSV or JSON Lines input.
+struct RowReader<R> {
+    reader: R,
+    format: Format,
+    line: usize,
+    header: Option<csv::StringRecord>,
+}
+
+impl<R: AsyncBufRead + Unpin> RowReader<R> {
+    fn new(reader: R, format: Format) -> Self {
+        Self {
+            reader,
+            format,
+            line: 0,
+            header: None,
+        }
+    }
+
+    /// Returns the next non-blank row, or `None` at end of input.
+    async fn next(&mut self) -> Result<Option<Pending>, Error> {
+        loop {
+            let line = self.line + 1;
+            let record = match self.read_record().await? {
+                Some(Ok(record)) => record,
+                Some(Err(e)) if self.format == Format::Csv && self.header.is_none() => {
+                    return Err(Error::InvalidArgument(format!("CSV header {}", e)));
+                }
+                Some(Err(e)) => {
+                    return Ok(Some(Pending::Rejected(RowResult::rejected(
+                        line,
+                        None,
+                        Outcome::Invalid,
+                        format!("row {}", e),
+                    ))));
+                }
+                None => return Ok(None),
+            };
+            if record.trim().is_empty() {
+                continue;
+            }
+
+            let row = match (self.format, &self.header) {
+                (Format::Jsonl, _) => {
+                    serde_json::from_str::<ImportRow>(&record).map_err(|e| e.to_string())
+                }
+                (Format::Csv, None) => {
+                    let header = parse_csv(&record).map_err(Error::InvalidArgument)?;
+                    if !header.iter().any(|field| field == "external_id") {
+                        return Err(Error::InvalidArgument(
+                            "CSV header must contain an external_id column".to_string(),
+                        ));
+                    }
+                    self.header = Some(header);
+                    continue;
+                }
+                (Format::Csv, Some(header)) => {
+                    parse_csv(&record).and_then(|fields| csv_row(header, &fields))
+                }
+            };
+
+            return Ok(Some(match row {
+                Ok(row) => match validate(&row) {
+                    Ok(()) => Pending::Import { line, row },
+                    Err(e) => Pending::Rejected(RowResult::rejected(
+                        line,
+                        Some(row.external_id),
+                        Outcome::Invalid,
+                        e,
+                    )),
+                },
+                Err(e) => Pending::Rejected(RowResult::rejected(line, None, Outcome::Invalid, e)),
+            }));
+        }
+    }
+
+    /// Reads one record, which for CSV may span several lines inside a quoted field. A record
+    /// that isn't valid UTF-8 is returned as an error message, so the caller can reject just
+    /// that row.
+    async fn read_record(&mut self) -> Result<Option<Result<String, String>>, Error> {
+        let mut record = Vec::new();
+        loop {
+            let read = self
+                .reader
+                .read_until(b'\n', &mut record)
+                .await
+                .map_err(Error::Io)?;
+            if read == 0 {
+                if record.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            }
+            self.line += 1;
+
+            let quotes = record.iter().filter(|byte| **byte == b'"').count();
+            if self.format != Format::Csv || quotes % 2 == 0 {
+                break;
+            }
+        }
+
+        Ok(Some(
+            String::from_utf8(record).map_err(|_| "is not valid UTF-8".to_string()),
+        ))
+    }
+}
+
+fn parse_csv(record: &str) -> Result<csv::StringRecord, String> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(record.as_bytes())
+        .records()
+        .next()
+        .unwrap_or_else(|| Ok(csv::StringRecord::new()))
+        .map_err(|e| e.to_string())
+}
+
+fn csv_row(header: &csv::StringRecord, fields: &csv::StringRecord) -> Result<ImportRow, String> {
+    let field = |name: &str| {
+        header
+            .iter()
+            .position(|h| h == name)
+            .and_then(|i| fields.get(i))
+            .filter(|value| !value.is_empty())
+    };
+
+    let user_metadata = field("user_metadata")
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(|e| format!("user_metadata is not valid JSON: {}", e))?;
+
+    Ok(ImportRow {
+        external_id: field("external_id").unwrap_or_default().to_string(),
+        user_metadata,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::test_server::{user_response, users_page, Request, TestServer, DROP};
+
+    async fn passage(request: Request) -> (u16, String) {
+        if request.method == "GET" {
+            if request.path.contains("identifier=existing") {
+                return (200, users_page(&["existing"], 1));
+            }
+            return (200, users_page(&[], 0));
+        }
+
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        let external_id = body["external_id"].as_str().unwrap();
+        if external_id == "broken" {
+            let error = r#"{"code":"internal_server_error","error":"boom"}"#;
+            return (500, error.to_string());
+        }
+        (201, user_response(external_id, serde_json::json!([])))
+    }
+
+    async fn import(
+        server: &TestServer,
+        input: &[u8],
+        format: Format,
+    ) -> Result<(ImportSummary, Vec<RowResult>), Error> {
+        let user = User::new(server.configuration());
+        let mut log = Vec::new();
+        let summary = import_users(
+            &user,
+            input,
+            &mut log,
+            ImportOptions {
+                format,
+                ..Default::default()
+            },
+        )
+        .await?;
+        let results = String::from_utf8(log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        Ok((summary, results))
+    }
+
+    #[tokio::test]
+    async fn imports_csv_rows_in_order() {
+        let server = TestServer::start(passage).await;
+        let input = b"external_id,user_metadata\n\
+            new,\"{\"\"plan\"\":\"\"pro\"\"}\"\n\
+            existing,\n\
+            new,\n\
+            \x20padded,\n\
+            listed,[1]\n\
+            broken,\n";
+        let (summary, results) = import(&server, input, Format::Csv).await.unwrap();
+
+        assert_eq!(
+            summary,
+            ImportSummary {
+                created: 1,
+                skipped: 2,
+                invalid: 2,
+                failed: 1,
+            }
+        );
+        let outcomes: Vec<(usize, Outcome)> = results.iter().map(|r| (r.line, r.outcome)).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (2, Outcome::Created),
+                (3, Outcome::Skipped),
+                (4, Outcome::Skipped),
+                (5, Outcome::Invalid),
+                (6, Outcome::Invalid),
+                (7, Outcome::Failed),
+            ]
+        );
+        assert_eq!(results[0].user_id.as_deref(), Some("id-new"));
+        assert_eq!(results[1].user_id.as_deref(), Some("id-existing"));
+
+        let created = server
+            .requests()
+            .into_iter()
+            .find(|r| r.method == "POST" && r.body.contains("\"new\""))
+            .unwrap();
+        assert!(created.body.contains(r#""user_metadata":{"plan":"pro"}"#));
+    }
+
+    #[tokio::test]
+    async fn failed_rows_use_a_single_retry_layer() {
+        let server = TestServer::start(passage).await;
+        let (summary, _) = import(&server, b"{\"external_id\":\"broken\"}\n", Format::Jsonl)
+            .await
+            .unwrap();
+        assert_eq!(summary.failed, 1);
+
+        let creates = server
+            .requests()
+            .iter()
+            .filter(|r| r.method == "POST")
+            .count();
+        assert_eq!(creates, 1);
+    }
+
+    /// A server on which `new` doesn't exist until created, and whose first create fails in
+    /// the given way.
+    async fn failing_first_create(status: u16) -> TestServer {
+        let creates = Arc::new(AtomicUsize::new(0));
+        TestServer::start(move |request| {
+            let creates = creates.clone();
+            async move {
+                if request.method == "GET" {
+                    return match creates.load(Ordering::SeqCst) {
+                        0 => (200, users_page(&[], 0)),
+                        _ => (200, users_page(&["new"], 1)),
+                    };
+                }
+                match creates.fetch_add(1, Ordering::SeqCst) {
+                    0 => (
+                        status,
+                        r#"{"code":"internal_server_error","error":"boom"}"#.to_string(),
+                    ),
+                    _ => (201, user_response("new", serde_json::json!([]))),
+                }
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn rows_whose_create_response_is_lost_are_created() {
+        let server = failing_first_create(DROP).await;
+        let (summary, results) = import(&server, b"{\"external_id\":\"new\"}\n", Format::Jsonl)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.created, 1);
+        assert_eq!(results[0].outcome, Outcome::Created);
+        assert_eq!(results[0].user_id.as_deref(), Some("id-new"));
+        let methods: Vec<String> = server.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(methods, ["GET", "POST", "GET"]);
+    }
+
+    #[tokio::test]
+    async fn creates_are_retried_with_the_same_idempotency_key() {
+        let server = failing_first_create(500).await;
+        let mut user = User::new(server.configuration());
+        user.configuration.client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(crate::call_options::idempotency_key)
+            .build();
+        let mut log = Vec::new();
+        let summary = import_users(
+            &user,
+            &b"{\"external_id\":\"new\"}\n"[..],
+            &mut log,
+            ImportOptions {
+                format: Format::Jsonl,
+                retry: Some(Retry::new(2).with_backoff(Duration::ZERO, Duration::ZERO)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.created, 1);
+        let keys: Vec<String> = server
+            .requests()
+            .iter()
+            .filter(|r| r.method == "POST")
+            .map(|r| r.header("Idempotency-Key").unwrap().to_string())
+            .collect();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0], keys[1]);
+        assert_eq!(keys[0], idempotency_key("new"));
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_rejects_only_that_row() {
+        let server = TestServer::start(passage).await;
+        let input = b"{\"external_id\":\"a\xff\"}\n\n{\"external_id\":\"new\"}\nnot json\n";
+        let (summary, results) = import(&server, input, Format::Jsonl).await.unwrap();
+
+        assert_eq!(summary.created, 1);
+        assert_eq!(summary.invalid, 2);
+        assert_eq!(results[0].line, 1);
+        assert_eq!(results[0].outcome, Outcome::Invalid);
+        assert_eq!(results[0].error.as_deref(), Some("row is not valid UTF-8"));
+        assert_eq!(results[1].line, 3);
+        assert_eq!(results[1].outcome, Outcome::Created);
+        assert_eq!(results[2].line, 4);
+    }
+
+    #[tokio::test]
+    async fn csv_requires_external_id_column() {
+        let server = TestServer::start(passage).await;
+        let result = import(&server, b"id,user_metadata\nnew,\n", Format::Csv).await;
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+
+        let result = import(&server, b"external_id\xff\nnew\n", Format::Csv).await;
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn validates_external_ids() {
+        let row = |external_id: &str| ImportRow {
+            external_id: external_id.to_string(),
+            user_metadata: None,
+        };
+        assert!(validate(&row("user-1")).is_ok());
+        assert!(validate(&row("")).is_err());
+        assert!(validate(&row(" user-1")).is_err());
+        assert!(validate(&row("user\t1")).is_err());
+        assert!(validate(&row(&"a".repeat(MAX_EXTERNAL_ID_LEN + 1))).is_err());
+    }
+
+    #[test]
+    fn csv_records_may_span_lines() {
+        let header = parse_csv("external_id,user_metadata\n").unwrap();
+        let fields = parse_csv("a,\"{\n\"\"k\"\": 1}\"\n").unwrap();
+        let row = csv_row(&header, &fields).unwrap();
+        assert_eq!(row.external_id, "a");
+        assert_eq!(row.user_metadata, Some(serde_json::json!({ "k": 1 })));
+    }
+}
@@ -11,6 +11,35 @@
 //!     std::env::var("PASSAGE_API_KEY").unwrap(),
 //! );
 //! ```
+//!
+//! # Tracing
+//!
+//! With the `tracing` feature enabled, every `Auth` and `User` operation opens a
+//! `passage_flex.operation` span, and every request to Passage a `passage_flex.http` span
+//! within it. Spans record the operation, status, latency, attempt number and a keyed digest
+//! of the external ID. API keys and nonces are never recorded.
+//!
+//! # Metrics
+//!
+//! With the `metrics` feature enabled, the crate emits metrics through the `metrics` facade,
+//! to be collected by whichever recorder the application installs, such as a Prometheus or
+//! OpenTelemetry exporter:
+//!
+//! * `passage_flex_operations_total` - Counter of `Auth` and `User` operations, labeled by
+//!   `operation`, `outcome` (`ok` or `error`) and `error`, the `Error` variant in snake case.
+//!   An operation that calls another, such as `user.last_successful_login` calling
+//!   `user.events`, is counted once, under its own name.
+//! * `passage_flex_operation_duration_seconds` - Histogram of operation latency, labeled by
+//!   `operation` and `outcome`.
+//! * `passage_flex_http_requests_total` - Counter of requests to Passage, labeled by
+//!   `endpoint`, such as `get_user`, and `status`.
+//! * `passage_flex_http_request_duration_seconds` - Histogram of request latency, labeled by
+//!   `endpoint`.
+//! * `passage_flex_circuit_breaker_state` - Gauge of each circuit breaker's state, labeled by
+//!   `breaker` (`auth` or `user`): 0 when closed, 1 when half-open and 2 when open.
+//! * `passage_flex_cache_lookups_total` - Counter of cache lookups, labeled by `result`
+//!   (`hit` or `miss`).
+//! * `passage_flex_cache_entries` - Gauge of the entries held by a `MemoryCache`.
 
 use std::fmt;
 
@@ -32,6 +61,28 @@ pub enum Error {
     InternalServerError,
     Other(String),
     InvalidArgument(String),
+    NonceReplayed,
+    Store(String),
+    TransactionNotFound,
+    TransactionExpired,
+    TransactionMismatch,
+    InvalidSessionToken(String),
+    SessionNotFound,
+    SessionExpired,
+    SessionRevoked,
+    InvalidStepUp(String),
+    TemporarilyLocked {
+        retry_after: std::time::Duration,
+    },
+    RateLimited {
+        retry_after: std::time::Duration,
+    },
+    TooManyRequests,
+    CircuitOpen,
+    Middleware(String),
+    DeadlineExceeded,
+    /// A copy of a transient `reqwest` error, shared by callers of a coalesced request.
+    Transient(String),
 }
 
 impl fmt::Display for Error {
@@ -50,18 +101,79 @@ impl fmt::Display for Error {
             Error::InternalServerError => ("response", "internal server error".to_string()),
             Error::Other(e) => ("response", e.to_string()),
             Error::InvalidArgument(e) => ("argument", e.to_string()),
+            Error::NonceReplayed => ("auth", "nonce has already been used".to_string()),
+            Error::Store(e) => ("store", e.to_string()),
+            Error::TransactionNotFound => ("auth", "transaction not found".to_string()),
+            Error::TransactionExpired => ("auth", "transaction expired".to_string()),
+            Error::TransactionMismatch => (
+                "auth",
+                "transaction was issued to a different session or user".to_string(),
+            ),
+            Error::InvalidSessionToken(e) => ("session", format!("invalid session token: {}", e)),
+            Error::SessionNotFound => ("session", "session not found".to_string()),
+            Error::SessionExpired => ("session", "session expired".to_string()),
+            Error::SessionRevoked => ("session", "session revoked".to_string()),
+            Error::InvalidStepUp(e) => ("step-up", e.to_string()),
+            Error::TemporarilyLocked { retry_after } => (
+                "auth",
+                format!(
+                    "temporarily locked, retry after {} seconds",
+                    retry_after.as_secs().max(1)
+                ),
+            ),
+            Error::TooManyRequests => ("response", "too many requests".to_string()),
+            Error::CircuitOpen => (
+                "circuit breaker",
+                "Passage is unavailable, failing fast".to_string(),
+            ),
+            Error::Middleware(e) => ("middleware", e.to_string()),
+            Error::DeadlineExceeded => ("deadline", "deadline exceeded".to_string()),
+            Error::Transient(e) => ("reqwest", e.to_string()),
+            Error::RateLimited { retry_after } => (
+                "rate limit",
+                format!(
+                    "too many requests, retry after {} seconds",
+                    retry_after.as_secs().max(1)
+                ),
+            ),
         };
         write!(f, "error in {}: {}", module, e)
     }
 }
 
+mod apis;
+pub mod cache;
+pub mod call_options;
+pub mod circuit_breaker;
+pub mod client_limiter;
+pub mod coalesce;
+mod digest;
+pub mod enumeration;
 mod error;
+pub mod export;
+pub mod import;
 pub mod models;
+pub mod nonce_store;
 
 #[rustfmt::skip]
 pub mod openapi;
 
 pub mod auth;
 pub mod passage_flex;
+pub mod privacy;
+pub mod rate_limit;
+pub mod report;
+#[cfg(feature = "session")]
+pub mod session;
+pub mod session_store;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod step_up;
+mod telemetry;
+#[cfg(test)]
+mod test_server;
+pub mod throttle;
+pub mod transaction_tracker;
 pub mod user;
 pub use passage_flex::PassageFlex;
+pub use reqwest_middleware;
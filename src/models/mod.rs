@@ -1,3 +1,9 @@
 mod passage_user;
+mod transaction;
+mod user_event;
+mod verified_nonce;
 
 pub use passage_user::PassageUser;
+pub use transaction::{FrontendTransaction, Transaction, TransactionKind};
+pub use user_event::{EventFilter, UserEvent};
+pub use verified_nonce::{VerifiedNonce, VerifyOptions};
@@ -27,3 +27,21 @@ pub struct PassageUser {
     #[serde(rename = "webauthn_types")]
     pub webauthn_types: Vec<crate::openapi::models::WebAuthnType>,
 }
+
+impl From<crate::openapi::models::UserInfo> for PassageUser {
+    fn from(user: crate::openapi::models::UserInfo) -> Self {
+        Self {
+            created_at: user.created_at,
+            external_id: user.external_id,
+            id: user.id,
+            last_login_at: user.last_login_at,
+            login_count: user.login_count,
+            status: user.status,
+            updated_at: user.updated_at,
+            user_metadata: user.user_metadata,
+            webauthn: user.webauthn,
+            webauthn_devices: user.webauthn_devices,
+            webauthn_types: user.webauthn_types,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The WebAuthn ceremony a transaction was created for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    Register,
+    Authenticate,
+}
+
+impl std::fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Register => write!(f, "register"),
+            Self::Authenticate => write!(f, "authenticate"),
+        }
+    }
+}
+
+/// A registration or authentication transaction created by Passage.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    /// The transaction ID to hand to the Passage frontend SDK.
+    pub id: String,
+    pub kind: TransactionKind,
+    /// The Passage application ID the transaction belongs to.
+    pub app_id: String,
+    /// The external ID of the user the transaction was created for. Not set for usernameless
+    /// authentication, where the user is only known once the nonce is verified.
+    pub external_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// When the transaction should no longer be accepted, if known.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(
+        id: String,
+        kind: TransactionKind,
+        app_id: String,
+        external_id: Option<String>,
+    ) -> Self {
+        Self {
+            id,
+            kind,
+            app_id,
+            external_id,
+            created_at: Utc::now(),
+            expires_at: None,
+        }
+    }
+
+    /// Whether the transaction has passed its expiry time.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    /// Whether the external ID returned by `Auth::verify_nonce` belongs to this transaction.
+    /// Usernameless transactions match any external ID.
+    pub fn matches(&self, external_id: &str) -> bool {
+        self.external_id
+            .as_deref()
+            .is_none_or(|expected| expected == external_id)
+    }
+
+    /// The parts of the transaction the frontend needs to run the ceremony.
+    pub fn to_frontend(&self) -> FrontendTransaction {
+        FrontendTransaction {
+            app_id: self.app_id.clone(),
+            transaction_id: self.id.clone(),
+            kind: self.kind,
+        }
+    }
+}
+
+/// The serializable form of a `Transaction` to send to the Passage frontend SDK.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrontendTransaction {
+    pub app_id: String,
+    pub transaction_id: String,
+    pub kind: TransactionKind,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(external_id: Option<&str>) -> Transaction {
+        Transaction::new(
+            "txn".to_string(),
+            TransactionKind::Authenticate,
+            "app".to_string(),
+            external_id.map(str::to_string),
+        )
+    }
+
+    #[test]
+    fn matches_its_own_user() {
+        let transaction = transaction(Some("user-1"));
+        assert!(transaction.matches("user-1"));
+        assert!(!transaction.matches("user-2"));
+    }
+
+    #[test]
+    fn usernameless_matches_any_user() {
+        assert!(transaction(None).matches("user-1"));
+    }
+
+    #[test]
+    fn expires_only_once_expiry_is_known_and_passed() {
+        let mut transaction = transaction(None);
+        assert!(!transaction.is_expired());
+        transaction.expires_at = Some(Utc::now() + chrono::Duration::minutes(5));
+        assert!(!transaction.is_expired());
+        transaction.expires_at = Some(Utc::now() - chrono::Duration::seconds(1));
+        assert!(transaction.is_expired());
+    }
+
+    #[test]
+    fn frontend_form_serializes_kind_in_snake_case() {
+        let frontend = serde_json::to_value(transaction(Some("user-1")).to_frontend()).unwrap();
+        assert_eq!(
+            frontend,
+            serde_json::json!({
+                "app_id": "app",
+                "transaction_id": "txn",
+                "kind": "authenticate",
+            })
+        );
+        assert_eq!(TransactionKind::Register.to_string(), "register");
+    }
+}
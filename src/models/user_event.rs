@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::openapi::models::{
+    SocialConnectionType, UserEventAction, UserEventStatus, UserRecentEvent,
+};
+use crate::Error;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UserEvent {
+    pub id: String,
+    pub action: UserEventAction,
+    pub status: UserEventStatus,
+    pub created_at: DateTime<Utc>,
+    /// When the event was completed. Not set for incomplete events.
+    pub completed_at: Option<DateTime<Utc>>,
+    pub ip_addr: String,
+    /// The raw user agent value from the originating device
+    pub user_agent: String,
+    /// A display-friendly version of the user agent
+    pub user_agent_display: String,
+    pub social_login_type: Option<SocialConnectionType>,
+}
+
+impl TryFrom<UserRecentEvent> for UserEvent {
+    type Error = Error;
+
+    fn try_from(event: UserRecentEvent) -> Result<Self, Self::Error> {
+        let parse = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|e| Error::Other(format!("invalid event timestamp {}: {}", value, e)))
+        };
+
+        Ok(Self {
+            created_at: parse(&event.created_at)?,
+            completed_at: event.completed_at.as_deref().map(parse).transpose()?,
+            id: event.id,
+            action: event.action,
+            status: event.status,
+            ip_addr: event.ip_addr,
+            user_agent: event.user_agent,
+            user_agent_display: event.user_agent_display,
+            social_login_type: event.social_login_type,
+        })
+    }
+}
+
+/// Criteria for selecting a user's recent events. Unset fields match every event.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventFilter {
+    pub action: Option<UserEventAction>,
+    pub status: Option<UserEventStatus>,
+    /// Only include events created at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only include events created before this time.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl EventFilter {
+    /// Whether the event satisfies every criterion of the filter.
+    pub fn matches(&self, event: &UserEvent) -> bool {
+        self.action.is_none_or(|action| event.action == action)
+            && self.status.is_none_or(|status| event.status == status)
+            && self.since.is_none_or(|since| event.created_at >= since)
+            && self.until.is_none_or(|until| event.created_at < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn recent_event(created_at: &str, completed_at: Option<&str>) -> UserRecentEvent {
+        serde_json::from_value(serde_json::json!({
+            "created_at": created_at,
+            "completed_at": completed_at,
+            "id": "event",
+            "ip_addr": "127.0.0.1",
+            "status": if completed_at.is_some() { "complete" } else { "incomplete" },
+            "type": "",
+            "user_agent": "",
+            "user_agent_display": "",
+            "action": "login",
+            "social_login_type": null,
+        }))
+        .unwrap()
+    }
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn converts_recent_events() {
+        let event = UserEvent::try_from(recent_event(
+            "2024-01-01T01:00:00+01:00",
+            Some("2024-01-01T00:00:30Z"),
+        ))
+        .unwrap();
+        assert_eq!(event.created_at, at(0));
+        assert_eq!(
+            event.completed_at,
+            Some(at(0) + chrono::Duration::seconds(30))
+        );
+        assert_eq!(event.action, UserEventAction::Login);
+        assert_eq!(event.status, UserEventStatus::Complete);
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        assert!(UserEvent::try_from(recent_event("yesterday", None)).is_err());
+        assert!(UserEvent::try_from(recent_event("2024-01-01T00:00:00Z", Some(""))).is_err());
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let event = UserEvent::try_from(recent_event("2024-01-01T00:00:00Z", None)).unwrap();
+        assert!(EventFilter::default().matches(&event));
+    }
+
+    #[test]
+    fn filter_requires_every_criterion() {
+        let event = UserEvent::try_from(recent_event("2024-01-01T02:00:00Z", None)).unwrap();
+        let filter = EventFilter {
+            action: Some(UserEventAction::Login),
+            status: Some(UserEventStatus::Incomplete),
+            since: Some(at(2)),
+            until: Some(at(3)),
+        };
+        assert!(filter.matches(&event));
+
+        let rejects = [
+            EventFilter {
+                action: Some(UserEventAction::Register),
+                ..filter.clone()
+            },
+            EventFilter {
+                status: Some(UserEventStatus::Complete),
+                ..filter.clone()
+            },
+            EventFilter {
+                since: Some(at(3)),
+                ..filter.clone()
+            },
+            EventFilter {
+                until: Some(at(2)),
+                ..filter.clone()
+            },
+        ];
+        for filter in rejects {
+            assert!(!filter.matches(&event), "{:?}", filter);
+        }
+    }
+}
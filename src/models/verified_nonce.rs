@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{PassageUser, TransactionKind};
+use crate::openapi::models::WebAuthnDevices;
+
+/// The result of a successfully verified nonce.
+///
+/// Passage only returns the external ID when verifying a nonce. The ceremony kind and device
+/// are best-effort guesses made from the user's most recent completed event and most recently
+/// created or used device, so they may describe a different ceremony if the user completed
+/// another one at about the same time. Don't make security decisions based on them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VerifiedNonce {
+    /// The external ID of the user who completed the ceremony.
+    pub external_id: String,
+    /// The ceremony the nonce was likely issued for, if requested with
+    /// `VerifyOptions::resolve_details` and the user has a recent completed event.
+    pub likely_kind: Option<TransactionKind>,
+    pub verified_at: DateTime<Utc>,
+    /// The passkey device that was likely just created or used, if requested with
+    /// `VerifyOptions::resolve_details`.
+    pub likely_device: Option<WebAuthnDevices>,
+    /// The user who completed the ceremony, if requested with `VerifyOptions::fetch_user`.
+    pub user: Option<Box<PassageUser>>,
+}
+
+/// Options controlling how much is resolved when verifying a nonce. By default only the nonce
+/// is verified, with no extra requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VerifyOptions {
+    /// Guess the ceremony kind and device. Requires two extra requests.
+    pub resolve_details: bool,
+    /// Include the `PassageUser`. Requires two extra requests, shared with `resolve_details`.
+    pub fetch_user: bool,
+}
@@ -0,0 +1,201 @@
+//! Local replay protection for verified nonces.
+//!
+//! Passage rejects a nonce that is verified twice, but a `NonceStore` lets your own server
+//! guarantee that a nonce it has accepted once can never produce a second session, even
+//! when requests are retried. When a store is configured, `Auth` checks it before calling
+//! Passage and records the nonce after a successful verification, returning
+//! `Error::NonceReplayed` for any nonce it has already seen.
+//!
+//! Nonces are stored as SHA-256 digests, never in plain text.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::nonce_store::MemoryNonceStore;
+//! use passage_flex::PassageFlex;
+//! use std::time::Duration;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_nonce_store(MemoryNonceStore::new(Duration::from_secs(24 * 60 * 60)));
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+
+use crate::Error;
+
+/// Records nonces that have been accepted so they can't be accepted again.
+pub trait NonceStore: Send + Sync {
+    /// Returns whether the nonce digest has already been recorded and not yet expired.
+    fn contains<'a>(&'a self, digest: &'a str) -> BoxFuture<'a, Result<bool, Error>>;
+
+    /// Records the nonce digest, returning `false` if it was already recorded.
+    ///
+    /// Implementations must check and record atomically so that two concurrent inserts of the
+    /// same digest can't both return `true`.
+    fn insert<'a>(&'a self, digest: &'a str) -> BoxFuture<'a, Result<bool, Error>>;
+}
+
+/// An in-memory `NonceStore` that forgets nonces after a fixed time to live.
+///
+/// Nonces are only tracked within a single process; use a shared store such as
+/// `SqliteNonceStore` when several instances serve the same users.
+pub struct MemoryNonceStore {
+    ttl: Duration,
+    nonces: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemoryNonceStore {
+    /// Creates a store that remembers each nonce for `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    fn contains<'a>(&'a self, digest: &'a str) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move {
+            let nonces = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+            Ok(nonces
+                .get(digest)
+                .is_some_and(|expires_at| *expires_at > Instant::now()))
+        })
+    }
+
+    fn insert<'a>(&'a self, digest: &'a str) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let mut nonces = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+            nonces.retain(|_, expires_at| *expires_at > now);
+            if nonces.contains_key(digest) {
+                return Ok(false);
+            }
+            nonces.insert(digest.to_string(), now + self.ttl);
+            Ok(true)
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteNonceStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use futures::future::BoxFuture;
+    use rusqlite::params;
+
+    use super::NonceStore;
+    use crate::sqlite::SqliteConnection;
+    use crate::Error;
+
+    /// A `NonceStore` backed by a SQLite database, shared by every process using the same file.
+    pub struct SqliteNonceStore {
+        ttl: Duration,
+        connection: SqliteConnection,
+    }
+
+    impl SqliteNonceStore {
+        /// Opens (or creates) the database at `path`, remembering each nonce for `ttl`.
+        pub fn open<P: AsRef<Path>>(path: P, ttl: Duration) -> Result<Self, Error> {
+            let connection = SqliteConnection::open(
+                path,
+                "CREATE TABLE IF NOT EXISTS passage_used_nonces (
+                    digest TEXT PRIMARY KEY,
+                    expires_at INTEGER NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS passage_used_nonces_expires_at
+                    ON passage_used_nonces (expires_at);",
+            )?;
+
+            Ok(Self { ttl, connection })
+        }
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    impl NonceStore for SqliteNonceStore {
+        fn contains<'a>(&'a self, digest: &'a str) -> BoxFuture<'a, Result<bool, Error>> {
+            let digest = digest.to_string();
+            Box::pin(self.connection.run(move |connection| {
+                connection.query_row(
+                    "SELECT EXISTS(SELECT 1 FROM passage_used_nonces WHERE digest = ?1 AND expires_at > ?2)",
+                    params![digest, now()],
+                    |row| row.get(0),
+                )
+            }))
+        }
+
+        fn insert<'a>(&'a self, digest: &'a str) -> BoxFuture<'a, Result<bool, Error>> {
+            let digest = digest.to_string();
+            let ttl = self.ttl.as_secs() as i64;
+            Box::pin(self.connection.run(move |connection| {
+                let now = now();
+                connection.execute(
+                    "DELETE FROM passage_used_nonces WHERE expires_at <= ?1",
+                    params![now],
+                )?;
+                let inserted = connection.execute(
+                    "INSERT OR IGNORE INTO passage_used_nonces (digest, expires_at) VALUES (?1, ?2)",
+                    params![digest, now + ttl],
+                )?;
+                Ok(inserted == 1)
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_store_records_each_nonce_once() {
+        let store = MemoryNonceStore::new(Duration::from_secs(60));
+        assert!(!store.contains("a").await.unwrap());
+        assert!(store.insert("a").await.unwrap());
+        assert!(store.contains("a").await.unwrap());
+        assert!(!store.insert("a").await.unwrap());
+        assert!(store.insert("b").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn memory_store_forgets_expired_nonces() {
+        let store = MemoryNonceStore::new(Duration::ZERO);
+        assert!(store.insert("a").await.unwrap());
+        assert!(!store.contains("a").await.unwrap());
+        assert!(store.insert("a").await.unwrap());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_store_records_each_nonce_once() {
+        let store = SqliteNonceStore::open(":memory:", Duration::from_secs(60)).unwrap();
+        assert!(!store.contains("a").await.unwrap());
+        assert!(store.insert("a").await.unwrap());
+        assert!(store.contains("a").await.unwrap());
+        assert!(!store.insert("a").await.unwrap());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_store_forgets_expired_nonces() {
+        let store = SqliteNonceStore::open(":memory:", Duration::ZERO).unwrap();
+        assert!(store.insert("a").await.unwrap());
+        assert!(!store.contains("a").await.unwrap());
+        assert!(store.insert("a").await.unwrap());
+    }
+}
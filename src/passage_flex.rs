@@ -1,6 +1,22 @@
-use crate::auth::Auth;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auth::{Auth, RegistrationPolicy};
+use crate::cache::CacheBackend;
+use crate::call_options::{self, Retry};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerPolicy};
+use crate::client_limiter::{ClientLimiter, ClientRateLimit};
+use crate::coalesce::Coalescing;
+use crate::enumeration::EnumerationProtection;
+use crate::nonce_store::NonceStore;
 use crate::openapi::apis::configuration::Configuration;
+use crate::rate_limit::{RateLimiter, RateLimits};
+use crate::session_store::SessionStore;
+use crate::step_up::{StepUp, StepUpPolicy};
+use crate::throttle::{Throttle, ThrottlePolicy};
+use crate::transaction_tracker::TransactionStore;
 use crate::user::User;
+use crate::Error;
 
 pub struct PassageFlex {
     app_id: String,
@@ -48,13 +64,18 @@ impl PassageFlex {
 
         let mut configuration = Configuration::new();
         configuration.bearer_access_token = Some(api_key);
-        configuration.client = reqwest::Client::builder()
+        let client = reqwest::Client::builder()
             .default_headers(headers)
             .build()
             .expect("Failed to create reqwest client for Passage");
+        configuration.client = reqwest_middleware::ClientBuilder::new(client)
+            .with(call_options::idempotency_key)
+            .build();
 
-        let auth = Auth::new(configuration.clone());
-        let user = User::new(configuration.clone());
+        let mut auth = Auth::new(configuration.clone());
+        auth.app_id = app_id.clone();
+        let mut user = User::new(configuration.clone());
+        user.app_id = app_id.clone();
 
         let mut client = Self { app_id, auth, user };
         // Set the default server URL
@@ -63,8 +84,360 @@ impl PassageFlex {
         client
     }
 
+    /// Sets the policy `Auth::begin` uses to decide whether a user may be registered.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The registration policy. Defaults to `RegistrationPolicy::Always`.
+    pub fn with_registration_policy(mut self, policy: RegistrationPolicy) -> Self {
+        self.auth.registration_policy = policy;
+        self
+    }
+
+    /// Sets the store `Auth` uses to reject nonces that were already accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The nonce store, such as a `MemoryNonceStore` or `SqliteNonceStore`.
+    pub fn with_nonce_store<S: NonceStore + 'static>(mut self, store: S) -> Self {
+        self.auth.nonce_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Sets the store `Auth` uses to track issued transactions and the sessions they belong to.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The transaction store, such as a `MemoryTransactionStore`.
+    /// * `ttl` - How long a tracked transaction may wait for its nonce.
+    pub fn with_transaction_store<S: TransactionStore + 'static>(
+        mut self,
+        store: S,
+        ttl: Duration,
+    ) -> Self {
+        self.auth.transaction_store = Some((Arc::new(store), ttl));
+        self
+    }
+
+    /// Sets the store used to record sessions and revoke them when the SDK revokes a user's
+    /// devices, deactivates the user, or deletes the user.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The session store, such as a `MemorySessionStore` or `SqliteSessionStore`.
+    /// * `ttl` - How long a session started with `Auth::start_session` stays valid.
+    pub fn with_session_store<S: SessionStore + 'static>(
+        mut self,
+        store: S,
+        ttl: Duration,
+    ) -> Self {
+        let store: Arc<dyn SessionStore> = Arc::new(store);
+        self.auth.session_store = Some((store.clone(), ttl));
+        self.user.session_store = Some(store);
+        self
+    }
+
+    /// Enables step-up authentication for sensitive actions.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The key used to sign step-up challenges and proofs, at least 32 bytes.
+    /// * `policy` - How recently each action requires a passkey ceremony.
+    ///
+    /// # Returns
+    ///
+    /// The client, or `Error::InvalidArgument` if `secret` is shorter than 32 bytes.
+    pub fn with_step_up(mut self, secret: &[u8], policy: StepUpPolicy) -> Result<Self, Error> {
+        self.auth.step_up = Some(StepUp::new(secret, policy)?);
+        Ok(self)
+    }
+
+    /// Enables lockout after repeated failed nonce verifications.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - How many failures lock a user or caller out, and for how long.
+    pub fn with_throttle(mut self, policy: ThrottlePolicy) -> Self {
+        self.auth.throttle = Some(Throttle::new(policy));
+        self
+    }
+
+    /// Enables protection against user enumeration through
+    /// `Auth::create_authenticate_transaction`, which then returns a usernameless transaction
+    /// for every user. Usernameless transactions rely on Passage behavior that its API spec
+    /// doesn't document; see `Auth::create_discoverable_authenticate_transaction`.
+    ///
+    /// # Arguments
+    ///
+    /// * `protection` - The settings created with `EnumerationProtection::new`.
+    pub fn with_enumeration_protection(mut self, protection: EnumerationProtection) -> Self {
+        self.auth.enumeration_protection = Some(protection);
+        self
+    }
+
+    /// Limits how often transactions can be created per user and per caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The rate limits for each transaction kind.
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.auth.rate_limiter = Some(RateLimiter::new(limits));
+        self
+    }
+
+    /// Limits the rate of all requests to Passage, sending interactive authentication requests
+    /// ahead of bulk user management requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The request rate to stay under.
+    pub fn with_client_rate_limit(mut self, limit: ClientRateLimit) -> Self {
+        let limiter = Arc::new(ClientLimiter::new(limit));
+        self.auth.client_limiter = Some(limiter.clone());
+        self.user.client_limiter = Some(limiter);
+        self
+    }
+
+    /// Adds circuit breakers that fail fast while Passage is unavailable, one for
+    /// authentication and one for user management.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - When the breakers open and how they recover.
+    pub fn with_circuit_breaker(mut self, policy: CircuitBreakerPolicy) -> Self {
+        self.auth.circuit_breaker = Some(Arc::new(CircuitBreaker::new("auth", policy)));
+        self.user.circuit_breaker = Some(Arc::new(CircuitBreaker::new("user", policy)));
+        self
+    }
+
+    /// Lets concurrent identical user reads share a single request to Passage.
+    pub fn with_request_coalescing(mut self) -> Self {
+        self.user.coalescing = Some(Coalescing::new());
+        self
+    }
+
+    /// Caches user and device reads, including the user lookups made under enumeration
+    /// protection, invalidating a user's entries whenever this client changes the user.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - The cache backend, such as a `MemoryCache`.
+    /// * `ttl` - How long a cached read is served.
+    pub fn with_cache<C: CacheBackend + 'static>(mut self, cache: C, ttl: Duration) -> Self {
+        let cache: Arc<dyn CacheBackend> = Arc::new(cache);
+        self.auth.cache = Some((cache.clone(), ttl));
+        self.user.cache = Some((cache, ttl));
+        self
+    }
+
+    /// Sets how often `Auth` and `User` attempt requests that fail with a transient error.
+    /// Individual calls can override it with `CallOptions::with_retry`.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The retry policy. Defaults to `Retry::none()`.
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.auth.retry = retry;
+        self.user.retry = retry;
+        self
+    }
+
+    /// Adds a middleware that sees every request sent to Passage and every response received,
+    /// such as to add headers, propagate trace context or log traffic.
+    ///
+    /// Middleware runs in the order it was added, once for each attempt of a request. Errors
+    /// returned by a middleware surface as `Error::Middleware`.
+    ///
+    /// # Arguments
+    ///
+    /// * `middleware` - A `reqwest_middleware::Middleware`, re-exported as
+    ///   `passage_flex::reqwest_middleware`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::reqwest_middleware::{Middleware, Next, Result};
+    /// use passage_flex::PassageFlex;
+    ///
+    /// struct TenantHeader(String);
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Middleware for TenantHeader {
+    ///     async fn handle(
+    ///         &self,
+    ///         mut req: reqwest::Request,
+    ///         extensions: &mut http::Extensions,
+    ///         next: Next<'_>,
+    ///     ) -> Result<reqwest::Response> {
+    ///         req.headers_mut()
+    ///             .insert("X-Tenant", self.0.parse().unwrap());
+    ///         next.run(req, extensions).await
+    ///     }
+    /// }
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// )
+    /// .with_middleware(TenantHeader("acme".to_string()));
+    /// ```
+    pub fn with_middleware<M: reqwest_middleware::Middleware>(mut self, middleware: M) -> Self {
+        let client =
+            reqwest_middleware::ClientBuilder::from_client(self.auth.configuration.client.clone())
+                .with(middleware)
+                .build();
+        self.user.configuration.client = client.clone();
+        self.auth.configuration.client = client;
+        self
+    }
+
     fn set_server_url(&mut self, server_url: String) {
         self.user.configuration.base_path = format!("{}/v1/apps/{}", server_url, self.app_id);
         self.auth.configuration.base_path = format!("{}/v1/apps/{}", server_url, self.app_id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future::BoxFuture;
+    use reqwest_middleware::Next;
+
+    use super::*;
+    use crate::test_server::{user_response, users_page, TestServer};
+
+    fn tag<'a>(
+        mut request: reqwest::Request,
+        extensions: &'a mut http::Extensions,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, reqwest_middleware::Result<reqwest::Response>> {
+        request
+            .headers_mut()
+            .insert("X-Order", reqwest::header::HeaderValue::from_static("a"));
+        next.run(request, extensions)
+    }
+
+    fn tag_again<'a>(
+        mut request: reqwest::Request,
+        extensions: &'a mut http::Extensions,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, reqwest_middleware::Result<reqwest::Response>> {
+        let order = request
+            .headers()
+            .get("X-Order")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        request
+            .headers_mut()
+            .insert("X-Order", format!("{order}b").parse().unwrap());
+        next.run(request, extensions)
+    }
+
+    fn reject<'a>(
+        _request: reqwest::Request,
+        _extensions: &'a mut http::Extensions,
+        _next: Next<'a>,
+    ) -> BoxFuture<'a, reqwest_middleware::Result<reqwest::Response>> {
+        Box::pin(async {
+            Err(reqwest_middleware::Error::middleware(
+                std::io::Error::other("rejected"),
+            ))
+        })
+    }
+
+    async fn user_server(failures: usize) -> TestServer {
+        let calls = Arc::new(AtomicUsize::new(0));
+        TestServer::start(move |request| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if call < failures {
+                    return (
+                        500,
+                        r#"{"code":"internal_server_error","error":"boom"}"#.to_string(),
+                    );
+                }
+                match request.path.as_str() {
+                    path if path.contains("/users?") => (200, users_page(&["user-1"], 1)),
+                    _ => (200, user_response("user-1", serde_json::json!([]))),
+                }
+            }
+        })
+        .await
+    }
+
+    fn passage_flex(server: &TestServer) -> PassageFlex {
+        let mut passage_flex = PassageFlex::new("app".to_string(), "key".to_string());
+        passage_flex.set_server_url(server.configuration().base_path);
+        passage_flex
+    }
+
+    #[tokio::test]
+    async fn middleware_runs_in_order_and_keeps_default_headers() {
+        let server = user_server(0).await;
+        let passage_flex = passage_flex(&server)
+            .with_middleware(tag)
+            .with_middleware(tag_again);
+
+        passage_flex.user.get("user-1".to_string()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for request in requests {
+            assert!(request.path.starts_with("/v1/apps/app/"));
+            assert_eq!(request.header("X-Order"), Some("ab"));
+            assert_eq!(request.header("Authorization"), Some("Bearer key"));
+            assert!(request
+                .header("Passage-Version")
+                .is_some_and(|version| version.starts_with("passage-flex-rust ")));
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_runs_for_every_attempt() {
+        let server = user_server(1).await;
+        let passage_flex = passage_flex(&server)
+            .with_retry(Retry::new(2).with_backoff(Duration::ZERO, Duration::ZERO))
+            .with_middleware(tag);
+
+        passage_flex.user.get("user-1".to_string()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|request| request.header("X-Order") == Some("a")));
+    }
+
+    #[tokio::test]
+    async fn middleware_errors_surface_as_middleware_errors() {
+        let server = user_server(0).await;
+        let passage_flex = passage_flex(&server).with_middleware(reject);
+
+        let error = passage_flex
+            .user
+            .get("user-1".to_string())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::Middleware(message) if message.contains("rejected")));
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn step_up_secrets_must_be_at_least_32_bytes() {
+        let client = || PassageFlex::new("app".to_string(), "key".to_string());
+        let policy = || StepUpPolicy::new(Duration::from_secs(60));
+
+        assert!(matches!(
+            client().with_step_up(b"", policy()),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            client().with_step_up(&[7; 31], policy()),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(client().with_step_up(&[7; 32], policy()).is_ok());
+    }
+}
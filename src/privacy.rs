@@ -0,0 +1,317 @@
+//! Data subject access and erasure workflows.
+//!
+//! [`export_subject`] gathers everything Passage holds about a user into a single
+//! self-contained document, and [`erase_subject`] revokes all of a user's passkeys and sessions
+//! and deletes the user. Erasure is idempotent: running it again for an already erased user
+//! succeeds and reports that nothing was left to remove.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::{privacy, PassageFlex};
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! );
+//!
+//! let external_id = "00000000-0000-0000-0000-000000000001";
+//! let export = privacy::export_subject(&passage_flex.user, external_id.to_string())
+//!     .await
+//!     .unwrap();
+//! std::fs::write("subject.json", export.to_json().unwrap()).unwrap();
+//!
+//! let receipt = privacy::erase_subject(&passage_flex.user, external_id.to_string())
+//!     .await
+//!     .unwrap();
+//! println!("{}", receipt.to_json().unwrap());
+//! ```
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::call_options::CallOptions;
+use crate::openapi::models::{UserInfo, UserRecentEvent, UserSocialConnections, WebAuthnDevices};
+use crate::user::User;
+use crate::Error;
+
+/// Everything Passage holds about a single user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SubjectExport {
+    /// When the export was generated.
+    pub exported_at: DateTime<Utc>,
+    pub external_id: String,
+    /// The user's profile, status and metadata.
+    pub user: UserInfo,
+    /// The user's passkey devices.
+    pub devices: Vec<WebAuthnDevices>,
+    /// The user's recent login and registration events.
+    pub recent_events: Vec<UserRecentEvent>,
+    /// The user's linked social accounts.
+    pub social_connections: UserSocialConnections,
+}
+
+impl SubjectExport {
+    /// Serializes the export as a pretty-printed JSON document.
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(Error::Serde)
+    }
+}
+
+/// A record of what an erasure removed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureReceipt {
+    pub external_id: String,
+    /// The Passage user ID, or `None` if no user with the external ID existed.
+    pub user_id: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+    /// The IDs of the passkey devices revoked by this erasure.
+    pub revoked_devices: Vec<String>,
+    /// Whether the user was deleted by this erasure.
+    pub user_deleted: bool,
+    /// Whether the user had already been erased before this erasure started.
+    pub already_erased: bool,
+}
+
+impl ErasureReceipt {
+    /// Serializes the receipt as a pretty-printed JSON document.
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(Error::Serde)
+    }
+}
+
+/// Gathers everything Passage holds about a user.
+///
+/// # Arguments
+///
+/// * `user` - The `User` client used to fetch the user and their devices.
+/// * `external_id` - The unique, immutable ID that represents the user.
+///
+/// # Returns
+///
+/// A `Result` containing the `SubjectExport` or an `Error`.
+pub async fn export_subject(user: &User, external_id: String) -> Result<SubjectExport, Error> {
+    let user_id = user.get_id(external_id.clone()).await?;
+    let info = user.get_info(&user_id).await?;
+    let devices = user.list_devices_by_id(&user_id).await?;
+
+    Ok(SubjectExport {
+        exported_at: Utc::now(),
+        external_id,
+        recent_events: info.recent_events.clone(),
+        social_connections: (*info.social_connections).clone(),
+        devices,
+        user: info,
+    })
+}
+
+/// Like `export_subject`, with options for this call.
+pub async fn export_subject_with_options(
+    user: &User,
+    external_id: String,
+    call_options: CallOptions,
+) -> Result<SubjectExport, Error> {
+    call_options.run(export_subject(user, external_id)).await
+}
+
+/// Revokes all of a user's passkey devices and deletes the user.
+///
+/// Devices or users that disappear while the erasure is running are treated as already
+/// removed, so an interrupted erasure can safely be retried.
+///
+/// # Arguments
+///
+/// * `user` - The `User` client used to revoke devices and delete the user.
+/// * `external_id` - The unique, immutable ID that represents the user.
+///
+/// # Returns
+///
+/// A `Result` containing the `ErasureReceipt` or an `Error`.
+pub async fn erase_subject(user: &User, external_id: String) -> Result<ErasureReceipt, Error> {
+    let mut receipt = ErasureReceipt {
+        external_id: external_id.clone(),
+        user_id: None,
+        started_at: Utc::now(),
+        completed_at: Utc::now(),
+        revoked_devices: Vec::new(),
+        user_deleted: false,
+        already_erased: false,
+    };
+
+    let user_id = match user.get_id(external_id.clone()).await {
+        Ok(user_id) => user_id,
+        Err(Error::UserNotFound) => {
+            user.revoke_sessions(&external_id).await?;
+            receipt.already_erased = true;
+            receipt.completed_at = Utc::now();
+            return Ok(receipt);
+        }
+        Err(e) => return Err(e),
+    };
+    receipt.user_id = Some(user_id.clone());
+
+    let devices = match user.list_devices_by_id(&user_id).await {
+        Ok(devices) => devices,
+        Err(Error::UserNotFound) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    for device in devices {
+        match user.revoke_device_by_id(&user_id, &device.id).await {
+            Ok(()) => receipt.revoked_devices.push(device.id),
+            Err(Error::DeviceNotFound) | Err(Error::UserNotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    match user.delete_by_id(&user_id).await {
+        Ok(()) => receipt.user_deleted = true,
+        Err(Error::UserNotFound) => {}
+        Err(e) => return Err(e),
+    }
+
+    user.revoke_sessions(&external_id).await?;
+    user.invalidate_cache(&external_id).await?;
+
+    receipt.already_erased = !receipt.user_deleted && receipt.revoked_devices.is_empty();
+    receipt.completed_at = Utc::now();
+    Ok(receipt)
+}
+
+/// Like `erase_subject`, with options for this call.
+pub async fn erase_subject_with_options(
+    user: &User,
+    external_id: String,
+    call_options: CallOptions,
+) -> Result<ErasureReceipt, Error> {
+    call_options.run(erase_subject(user, external_id)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_server::{device, user_response, users_page, Request, TestServer};
+
+    const NOT_FOUND: &str = r#"{"code":"user_not_found","error":"user not found"}"#;
+    const DEVICE_NOT_FOUND: &str = r#"{"code":"device_not_found","error":"device not found"}"#;
+
+    /// A Passage app holding at most one user, `subject`, with the given devices.
+    #[derive(Default)]
+    struct App {
+        exists: bool,
+        devices: Vec<String>,
+    }
+
+    fn handle(app: &Mutex<App>, request: &Request) -> (u16, String) {
+        let mut app = app.lock().unwrap();
+        let devices = || {
+            serde_json::Value::Array(
+                app.devices
+                    .iter()
+                    .map(|id| device(id, 1, "2024-01-01T00:00:00Z"))
+                    .collect(),
+            )
+        };
+        let path = request.path.as_str();
+        match (request.method.as_str(), app.exists) {
+            ("GET", exists) if path.starts_with("/users?") => {
+                let ids: &[&str] = if exists { &["subject"] } else { &[] };
+                (200, users_page(ids, ids.len() as i64))
+            }
+            (_, false) => (404, NOT_FOUND.to_string()),
+            ("GET", true) if path == "/users/id-subject" => {
+                (200, user_response("subject", devices()))
+            }
+            ("GET", true) => (200, serde_json::json!({ "devices": devices() }).to_string()),
+            ("DELETE", true) if path == "/users/id-subject" => {
+                app.exists = false;
+                (200, "{}".to_string())
+            }
+            ("DELETE", true) => {
+                let id = path.rsplit('/').next().unwrap();
+                if id == "gone" {
+                    return (404, DEVICE_NOT_FOUND.to_string());
+                }
+                app.devices.retain(|device| device != id);
+                (200, "{}".to_string())
+            }
+            _ => (405, "{}".to_string()),
+        }
+    }
+
+    async fn start(devices: &[&str]) -> (TestServer, Arc<Mutex<App>>) {
+        let app = Arc::new(Mutex::new(App {
+            exists: true,
+            devices: devices.iter().map(|id| id.to_string()).collect(),
+        }));
+        let server = TestServer::start({
+            let app = app.clone();
+            move |request| {
+                let response = handle(&app, &request);
+                async move { response }
+            }
+        })
+        .await;
+        (server, app)
+    }
+
+    #[tokio::test]
+    async fn exports_user_and_devices() {
+        let (server, _) = start(&["laptop", "phone"]).await;
+        let user = User::new(server.configuration());
+
+        let export = export_subject(&user, "subject".to_string()).await.unwrap();
+        assert_eq!(export.external_id, "subject");
+        assert_eq!(export.user.id, "id-subject");
+        let devices: Vec<&str> = export.devices.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(devices, ["laptop", "phone"]);
+        assert!(export.to_json().unwrap().contains("\"laptop\""));
+    }
+
+    #[tokio::test]
+    async fn export_of_unknown_user_fails() {
+        let (server, app) = start(&[]).await;
+        app.lock().unwrap().exists = false;
+        let user = User::new(server.configuration());
+
+        assert!(matches!(
+            export_subject(&user, "subject".to_string()).await,
+            Err(Error::UserNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn erases_devices_then_user() {
+        let (server, app) = start(&["laptop", "gone", "phone"]).await;
+        let user = User::new(server.configuration());
+
+        let receipt = erase_subject(&user, "subject".to_string()).await.unwrap();
+        assert_eq!(receipt.user_id.as_deref(), Some("id-subject"));
+        assert_eq!(receipt.revoked_devices, ["laptop", "phone"]);
+        assert!(receipt.user_deleted);
+        assert!(!receipt.already_erased);
+        assert!(!app.lock().unwrap().exists);
+
+        let deleted_user_last = server
+            .requests()
+            .iter()
+            .rfind(|r| r.method == "DELETE")
+            .map(|r| r.path.clone());
+        assert_eq!(deleted_user_last.as_deref(), Some("/users/id-subject"));
+    }
+
+    #[tokio::test]
+    async fn erasing_twice_reports_already_erased() {
+        let (server, _) = start(&["laptop"]).await;
+        let user = User::new(server.configuration());
+
+        erase_subject(&user, "subject".to_string()).await.unwrap();
+        let receipt = erase_subject(&user, "subject".to_string()).await.unwrap();
+        assert_eq!(receipt.user_id, None);
+        assert!(receipt.revoked_devices.is_empty());
+        assert!(!receipt.user_deleted);
+        assert!(receipt.already_erased);
+    }
+}
@@ -0,0 +1,309 @@
+//! Local rate limiting of transaction creation.
+//!
+//! Every call to `Auth::create_register_transaction` or
+//! `Auth::create_authenticate_transaction` is a request against your Passage quota, made on
+//! behalf of whoever reached your endpoint. With `RateLimits` configured, each transaction kind
+//! can be limited per external ID and, via `Auth::for_caller`, per caller key such as a client
+//! IP address or tenant. Limits are token buckets: a bucket holds up to `capacity` requests and
+//! refills completely over `period`. A request that would exceed any applicable limit fails
+//! with `Error::RateLimited` before anything is sent to Passage.
+//!
+//! Buckets are kept in memory, per process. External IDs and caller keys are kept as SHA-256
+//! digests, never in plain text.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::models::TransactionKind;
+//! use passage_flex::rate_limit::{Rate, RateLimits};
+//! use passage_flex::{Error, PassageFlex};
+//! use std::time::Duration;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_rate_limits(
+//!     RateLimits::new()
+//!         .per_user(TransactionKind::Authenticate, Rate::new(5, Duration::from_secs(60)))
+//!         .per_caller(TransactionKind::Authenticate, Rate::new(30, Duration::from_secs(60)))
+//!         .per_caller(TransactionKind::Register, Rate::new(3, Duration::from_secs(60 * 60))),
+//! );
+//!
+//! match passage_flex
+//!     .auth
+//!     .for_caller(&client_ip)
+//!     .create_authenticate_transaction(external_id)
+//!     .await
+//! {
+//!     Ok(transaction) => {
+//!         // send to the frontend
+//!     }
+//!     Err(Error::RateLimited { retry_after }) => {
+//!         // respond with 429 and a Retry-After header
+//!     }
+//!     Err(err) => {
+//!         // transaction couldn't be created
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::digest::sha256_hex;
+use crate::models::TransactionKind;
+use crate::Error;
+
+/// A token bucket size and refill period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rate {
+    capacity: u32,
+    period: Duration,
+}
+
+impl Rate {
+    /// Allows bursts of up to `capacity` requests, refilling completely over `period`.
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            period,
+        }
+    }
+
+    /// Tokens added per second.
+    fn refill_rate(&self) -> f64 {
+        match self.period.as_secs_f64() {
+            period if period > 0.0 => f64::from(self.capacity) / period,
+            _ => f64::INFINITY,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+enum Scope {
+    User,
+    Caller,
+}
+
+/// Rate limits per transaction kind, per external ID and per caller key.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits {
+    rates: HashMap<(Scope, TransactionKind), Rate>,
+}
+
+impl RateLimits {
+    /// Creates a set of limits that allows everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits how often transactions of a kind can be created for the same external ID.
+    pub fn per_user(mut self, kind: TransactionKind, rate: Rate) -> Self {
+        self.rates.insert((Scope::User, kind), rate);
+        self
+    }
+
+    /// Limits how often transactions of a kind can be created by the same caller key.
+    pub fn per_caller(mut self, kind: TransactionKind, rate: Rate) -> Self {
+        self.rates.insert((Scope::Caller, kind), rate);
+        self
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: &Rate, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.refill_rate()).min(f64::from(rate.capacity));
+        self.updated_at = now;
+    }
+}
+
+/// Enforces `RateLimits`.
+pub(crate) struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<HashMap<(Scope, TransactionKind, String), Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from every bucket that applies to the request, or from none of them if
+    /// any is empty.
+    pub(crate) fn acquire(
+        &self,
+        kind: TransactionKind,
+        external_id: Option<&str>,
+        caller: Option<&str>,
+    ) -> Result<(), Error> {
+        let keys: Vec<_> = [(Scope::User, external_id), (Scope::Caller, caller)]
+            .into_iter()
+            .filter_map(|(scope, key)| {
+                let rate = self.limits.rates.get(&(scope, kind))?;
+                Some(((scope, kind, sha256_hex(key?)), *rate))
+            })
+            .collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets.retain(|(scope, kind, _), bucket| {
+            self.limits
+                .rates
+                .get(&(*scope, *kind))
+                .is_some_and(|rate| now.duration_since(bucket.updated_at) < rate.period)
+        });
+
+        let mut retry_after = Duration::ZERO;
+        for (key, rate) in &keys {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: f64::from(rate.capacity),
+                updated_at: now,
+            });
+            bucket.refill(rate, now);
+            if bucket.tokens < 1.0 {
+                let wait = (1.0 - bucket.tokens) / rate.refill_rate();
+                retry_after = retry_after.max(Duration::from_secs_f64(wait));
+            }
+        }
+
+        if !retry_after.is_zero() {
+            return Err(Error::RateLimited { retry_after });
+        }
+
+        for (key, _) in &keys {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limits: RateLimits) -> RateLimiter {
+        RateLimiter::new(limits)
+    }
+
+    #[test]
+    fn bursts_up_to_capacity_then_limits() {
+        let limiter = limiter(RateLimits::new().per_user(
+            TransactionKind::Authenticate,
+            Rate::new(2, Duration::from_secs(60)),
+        ));
+
+        for _ in 0..2 {
+            assert!(limiter
+                .acquire(TransactionKind::Authenticate, Some("user-1"), None)
+                .is_ok());
+        }
+        match limiter.acquire(TransactionKind::Authenticate, Some("user-1"), None) {
+            Err(Error::RateLimited { retry_after }) => {
+                assert!(retry_after <= Duration::from_secs(30));
+                assert!(retry_after > Duration::from_secs(29));
+            }
+            other => panic!("expected a rate limit, got {:?}", other),
+        }
+
+        assert!(limiter
+            .acquire(TransactionKind::Authenticate, Some("user-2"), None)
+            .is_ok());
+        assert!(limiter
+            .acquire(TransactionKind::Register, Some("user-1"), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn buckets_refill_over_the_period() {
+        let limiter = limiter(RateLimits::new().per_user(
+            TransactionKind::Register,
+            Rate::new(1, Duration::from_millis(20)),
+        ));
+
+        assert!(limiter
+            .acquire(TransactionKind::Register, Some("user-1"), None)
+            .is_ok());
+        assert!(limiter
+            .acquire(TransactionKind::Register, Some("user-1"), None)
+            .is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(limiter
+            .acquire(TransactionKind::Register, Some("user-1"), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn a_limited_request_takes_no_tokens() {
+        let limiter = limiter(
+            RateLimits::new()
+                .per_user(
+                    TransactionKind::Authenticate,
+                    Rate::new(1, Duration::from_secs(60)),
+                )
+                .per_caller(
+                    TransactionKind::Authenticate,
+                    Rate::new(1, Duration::from_secs(60)),
+                ),
+        );
+
+        assert!(limiter
+            .acquire(
+                TransactionKind::Authenticate,
+                Some("user-1"),
+                Some("10.0.0.1")
+            )
+            .is_ok());
+        // The caller's bucket is empty, so user-2's bucket must be left untouched.
+        assert!(limiter
+            .acquire(
+                TransactionKind::Authenticate,
+                Some("user-2"),
+                Some("10.0.0.1")
+            )
+            .is_err());
+        assert!(limiter
+            .acquire(
+                TransactionKind::Authenticate,
+                Some("user-2"),
+                Some("10.0.0.2")
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn unconfigured_kinds_and_missing_keys_are_unlimited() {
+        let limiter = limiter(RateLimits::new().per_caller(
+            TransactionKind::Register,
+            Rate::new(1, Duration::from_secs(60)),
+        ));
+
+        for _ in 0..3 {
+            assert!(limiter
+                .acquire(
+                    TransactionKind::Authenticate,
+                    Some("user-1"),
+                    Some("10.0.0.1")
+                )
+                .is_ok());
+            assert!(limiter
+                .acquire(TransactionKind::Register, Some("user-1"), None)
+                .is_ok());
+        }
+    }
+}
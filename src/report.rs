@@ -0,0 +1,417 @@
+//! Fleet-wide passkey adoption and device analytics.
+//!
+//! A report walks every user in the app along with their passkey devices and aggregates
+//! how many users have adopted passkeys, which credential types they use, and how those
+//! devices are being used.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::{report, PassageFlex};
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! );
+//!
+//! let adoption = report::generate(&passage_flex.user).await.unwrap();
+//! println!("{:.1}% of users have a passkey", adoption.adoption_rate * 100.0);
+//! std::fs::write("adoption.csv", adoption.to_csv().unwrap()).unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::call_options::CallOptions;
+use crate::openapi::models::{UserInfo, WebAuthnType};
+use crate::user::User;
+use crate::Error;
+
+/// Number of users requested per page while walking the app's users.
+const PAGE_LIMIT: i32 = 100;
+
+/// Number of user detail requests allowed in flight at once.
+const CONCURRENCY: usize = 8;
+
+/// Upper bounds (inclusive) of the `usage_count` buckets; anything larger lands in a final open
+/// bucket.
+const USAGE_COUNT_BUCKETS: [(i32, &str); 5] = [
+    (0, "0"),
+    (1, "1"),
+    (5, "2-5"),
+    (20, "6-20"),
+    (100, "21-100"),
+];
+
+/// Upper bounds (inclusive, in days) of the last-use recency buckets.
+const RECENCY_BUCKETS: [(i64, &str); 4] =
+    [(7, "0-7d"), (30, "8-30d"), (90, "31-90d"), (365, "91-365d")];
+
+/// Aggregated passkey adoption and device statistics for an app.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AdoptionReport {
+    /// When the report was generated.
+    pub generated_at: DateTime<Utc>,
+    /// Total number of users in the app.
+    pub total_users: u64,
+    /// Number of users with `webauthn == true`.
+    pub webauthn_users: u64,
+    /// Share of users with `webauthn == true`, between `0.0` and `1.0`.
+    pub adoption_rate: f64,
+    /// Number of users with exactly one passkey device.
+    pub single_passkey_users: u64,
+    /// Total number of passkey devices across all users.
+    pub total_devices: u64,
+    /// Number of devices of each credential type.
+    pub device_types: BTreeMap<WebAuthnType, u64>,
+    /// Number of users keyed by how many devices they have.
+    pub devices_per_user: BTreeMap<usize, u64>,
+    /// Number of devices per `usage_count` bucket.
+    pub usage_count: Vec<Bucket>,
+    /// Number of devices per bucket of days since the device was last used.
+    pub last_used: Vec<Bucket>,
+}
+
+/// A labeled histogram bucket.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bucket {
+    pub label: String,
+    pub count: u64,
+}
+
+impl AdoptionReport {
+    fn new(generated_at: DateTime<Utc>) -> Self {
+        let buckets = |labels: &[&str]| {
+            labels
+                .iter()
+                .map(|label| Bucket {
+                    label: label.to_string(),
+                    count: 0,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut usage_labels: Vec<&str> = USAGE_COUNT_BUCKETS.iter().map(|(_, l)| *l).collect();
+        usage_labels.push("101+");
+        let mut recency_labels: Vec<&str> = RECENCY_BUCKETS.iter().map(|(_, l)| *l).collect();
+        recency_labels.extend(["366d+", "unknown"]);
+
+        Self {
+            generated_at,
+            total_users: 0,
+            webauthn_users: 0,
+            adoption_rate: 0.0,
+            single_passkey_users: 0,
+            total_devices: 0,
+            device_types: BTreeMap::new(),
+            devices_per_user: BTreeMap::new(),
+            usage_count: buckets(&usage_labels),
+            last_used: buckets(&recency_labels),
+        }
+    }
+
+    fn record(&mut self, user: &UserInfo) {
+        self.total_users += 1;
+        if user.webauthn {
+            self.webauthn_users += 1;
+        }
+
+        let devices = &user.webauthn_devices;
+        *self.devices_per_user.entry(devices.len()).or_default() += 1;
+        if devices.len() == 1 {
+            self.single_passkey_users += 1;
+        }
+
+        for device in devices {
+            self.total_devices += 1;
+            *self.device_types.entry(device.r#type).or_default() += 1;
+
+            let usage_index = USAGE_COUNT_BUCKETS
+                .iter()
+                .position(|(max, _)| device.usage_count <= *max)
+                .unwrap_or(USAGE_COUNT_BUCKETS.len());
+            self.usage_count[usage_index].count += 1;
+
+            let recency_index = match DateTime::parse_from_rfc3339(&device.last_login_at) {
+                Ok(last_login_at) => {
+                    let days = (self.generated_at - last_login_at.with_timezone(&Utc)).num_days();
+                    RECENCY_BUCKETS
+                        .iter()
+                        .position(|(max, _)| days <= *max)
+                        .unwrap_or(RECENCY_BUCKETS.len())
+                }
+                Err(_) => RECENCY_BUCKETS.len() + 1,
+            };
+            self.last_used[recency_index].count += 1;
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        if self.total_users > 0 {
+            self.adoption_rate = self.webauthn_users as f64 / self.total_users as f64;
+        }
+        self
+    }
+
+    /// Serializes the report as a pretty-printed JSON document.
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(Error::Serde)
+    }
+
+    /// Serializes the report as CSV with `metric,key,value` rows.
+    pub fn to_csv(&self) -> Result<String, Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let mut row = |metric: &str, key: &str, value: String| {
+            writer
+                .write_record([metric, key, value.as_str()])
+                .map_err(|e| Error::Other(e.to_string()))
+        };
+
+        row("metric", "key", "value".to_string())?;
+        row("generated_at", "", self.generated_at.to_rfc3339())?;
+        row("total_users", "", self.total_users.to_string())?;
+        row("webauthn_users", "", self.webauthn_users.to_string())?;
+        row("adoption_rate", "", self.adoption_rate.to_string())?;
+        row(
+            "single_passkey_users",
+            "",
+            self.single_passkey_users.to_string(),
+        )?;
+        row("total_devices", "", self.total_devices.to_string())?;
+        for (device_type, count) in &self.device_types {
+            row("device_types", &device_type.to_string(), count.to_string())?;
+        }
+        for (devices, count) in &self.devices_per_user {
+            row("devices_per_user", &devices.to_string(), count.to_string())?;
+        }
+        for bucket in &self.usage_count {
+            row("usage_count", &bucket.label, bucket.count.to_string())?;
+        }
+        for bucket in &self.last_used {
+            row("last_used", &bucket.label, bucket.count.to_string())?;
+        }
+
+        let bytes = writer
+            .into_inner()
+            .map_err(|e| Error::Other(e.to_string()))?;
+        String::from_utf8(bytes).map_err(|e| Error::Other(e.to_string()))
+    }
+}
+
+/// Walks every user in the app and aggregates their passkey adoption and device statistics.
+///
+/// # Arguments
+///
+/// * `user` - The `User` client used to list users and fetch their details.
+///
+/// # Returns
+///
+/// A `Result` containing the `AdoptionReport` or an `Error`.
+pub async fn generate(user: &User) -> Result<AdoptionReport, Error> {
+    let mut report = AdoptionReport::new(Utc::now());
+    let mut created_before = None;
+    let mut page = 1;
+
+    loop {
+        let response = user.list_page(page, PAGE_LIMIT, created_before).await?;
+        // Pin every following page to the first page's time anchor so users created
+        // mid-walk don't shift the pages underneath us.
+        created_before.get_or_insert(response.created_before);
+
+        let fetched = response.users.len();
+        let infos: Vec<UserInfo> = futures::stream::iter(response.users)
+            .map(|item| async move { user.get_info(&item.id).await })
+            .buffer_unordered(CONCURRENCY)
+            .try_collect()
+            .await?;
+        infos.iter().for_each(|info| report.record(info));
+
+        if fetched < PAGE_LIMIT as usize || report.total_users >= response.total_users as u64 {
+            break;
+        }
+        page += 1;
+    }
+
+    Ok(report.finish())
+}
+
+/// Like `generate`, with options for this call.
+pub async fn generate_with_options(
+    user: &User,
+    call_options: CallOptions,
+) -> Result<AdoptionReport, Error> {
+    call_options.run(generate(user)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, user_response, users_page, TestServer};
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn generated_at() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap()
+    }
+
+    fn device(r#type: &str, usage_count: i32, last_login_at: &str) -> serde_json::Value {
+        json!({
+            "created_at": "2024-01-01T00:00:00Z",
+            "cred_id": "cred",
+            "friendly_name": "device",
+            "id": "device",
+            "last_login_at": last_login_at,
+            "type": r#type,
+            "updated_at": "2024-01-01T00:00:00Z",
+            "usage_count": usage_count,
+            "icons": { "light": null, "dark": null },
+        })
+    }
+
+    fn user(webauthn: bool, devices: Vec<serde_json::Value>) -> UserInfo {
+        serde_json::from_value(json!({
+            "created_at": "2024-01-01T00:00:00Z",
+            "email": "",
+            "email_verified": false,
+            "external_id": "user",
+            "id": "user",
+            "last_login_at": "2024-01-01T00:00:00Z",
+            "login_count": 0,
+            "phone": "",
+            "phone_verified": false,
+            "recent_events": [],
+            "social_connections": {},
+            "status": "active",
+            "updated_at": "2024-01-01T00:00:00Z",
+            "user_metadata": null,
+            "webauthn": webauthn,
+            "webauthn_devices": devices,
+            "webauthn_types": [],
+        }))
+        .unwrap()
+    }
+
+    fn count(buckets: &[Bucket], label: &str) -> u64 {
+        buckets.iter().find(|b| b.label == label).unwrap().count
+    }
+
+    #[test]
+    fn empty_report_has_zero_adoption() {
+        let report = AdoptionReport::new(generated_at()).finish();
+        assert_eq!(report.total_users, 0);
+        assert_eq!(report.adoption_rate, 0.0);
+        assert_eq!(report.usage_count.len(), 6);
+        assert_eq!(report.last_used.len(), 6);
+    }
+
+    #[test]
+    fn records_users_and_devices() {
+        let mut report = AdoptionReport::new(generated_at());
+        report.record(&user(
+            true,
+            vec![device("passkey", 3, "2024-05-30T00:00:00Z")],
+        ));
+        report.record(&user(
+            true,
+            vec![
+                device("platform", 0, "2024-03-01T00:00:00Z"),
+                device("passkey", 500, "not a timestamp"),
+            ],
+        ));
+        report.record(&user(false, vec![]));
+        let report = report.finish();
+
+        assert_eq!(report.total_users, 3);
+        assert_eq!(report.webauthn_users, 2);
+        assert!((report.adoption_rate - 2.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(report.single_passkey_users, 1);
+        assert_eq!(report.total_devices, 3);
+        assert_eq!(report.device_types[&WebAuthnType::Passkey], 2);
+        assert_eq!(report.device_types[&WebAuthnType::Platform], 1);
+        assert_eq!(report.devices_per_user[&0], 1);
+        assert_eq!(report.devices_per_user[&1], 1);
+        assert_eq!(report.devices_per_user[&2], 1);
+
+        assert_eq!(count(&report.usage_count, "0"), 1);
+        assert_eq!(count(&report.usage_count, "2-5"), 1);
+        assert_eq!(count(&report.usage_count, "101+"), 1);
+        assert_eq!(count(&report.last_used, "0-7d"), 1);
+        assert_eq!(count(&report.last_used, "91-365d"), 1);
+        assert_eq!(count(&report.last_used, "unknown"), 1);
+    }
+
+    #[test]
+    fn csv_has_a_row_per_metric() {
+        let mut report = AdoptionReport::new(generated_at());
+        report.record(&user(true, vec![device("security_key", 1, "")]));
+        let csv = report.finish().to_csv().unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("metric,key,value"));
+        assert!(csv.contains("total_users,,1\n"));
+        assert!(csv.contains("adoption_rate,,1\n"));
+        assert!(csv.contains("device_types,security_key,1\n"));
+        assert!(csv.contains("usage_count,1,1\n"));
+        assert!(csv.contains("last_used,unknown,1\n"));
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let mut report = AdoptionReport::new(generated_at());
+        report.record(&user(true, vec![device("passkey", 7, "")]));
+        let report = report.finish();
+        let parsed: AdoptionReport = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    #[tokio::test]
+    async fn generate_aggregates_every_page() {
+        // A full first page, so the walk continues, then a partial last page. Users with an
+        // even number have one passkey.
+        let server = TestServer::start(|request| async move {
+            if let Some(id) = request.path.strip_prefix("/users/id-user-") {
+                let number: usize = id.parse().unwrap();
+                let devices = match number % 2 {
+                    0 => json!([test_server::device("phone", 3, "2024-01-01T00:00:00Z")]),
+                    _ => json!([]),
+                };
+                return (200, user_response(&format!("user-{number}"), devices));
+            }
+            let numbers = match request.path.contains("page=1&") {
+                true => 0..PAGE_LIMIT as usize,
+                false => PAGE_LIMIT as usize..150,
+            };
+            let ids: Vec<String> = numbers.map(|number| format!("user-{number}")).collect();
+            let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+            (200, users_page(&ids, 150))
+        })
+        .await;
+        let user = User::new(server.configuration());
+
+        let report = generate(&user).await.unwrap();
+
+        assert_eq!(report.total_users, 150);
+        assert_eq!(report.webauthn_users, 75);
+        assert_eq!(report.adoption_rate, 0.5);
+        assert_eq!(report.single_passkey_users, 75);
+        assert_eq!(report.total_devices, 75);
+        assert_eq!(report.devices_per_user[&0], 75);
+        assert_eq!(report.devices_per_user[&1], 75);
+        assert_eq!(count(&report.usage_count, "2-5"), 75);
+
+        let pages: Vec<String> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .filter(|path| path.starts_with("/users?"))
+            .collect();
+        assert_eq!(pages.len(), 2);
+        assert!(pages[0].contains("page=1&"));
+        assert!(!pages[0].contains("created_before"));
+        assert!(pages[1].contains("page=2&"));
+        assert!(pages[1].contains("created_before=1700000000"));
+    }
+}
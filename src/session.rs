@@ -0,0 +1,464 @@
+//! First-party session tokens issued after a nonce is verified.
+//!
+//! Passage verifies the passkey ceremony; your application still needs its own session. A
+//! `TokenIssuer` mints signed JWTs for the external ID returned by `Auth::verify_nonce`, and
+//! a `TokenVerifier` checks them on later requests. Tokens are signed with HS256 or EdDSA
+//! (Ed25519) and carry the signing key's ID in their header, so the verifier can hold several
+//! keys at once while keys are rotated.
+//!
+//! Requires the `session` feature.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::session::{SigningKey, TokenIssuer, TokenVerifier, VerificationKey};
+//! use std::time::Duration;
+//!
+//! let secret = std::env::var("SESSION_SECRET").unwrap().into_bytes();
+//! let issuer = TokenIssuer::new(SigningKey::hs256("2024-10", &secret)?, Duration::from_secs(3600))
+//!     .with_issuer("https://example.com");
+//! let verifier = TokenVerifier::new(vec![VerificationKey::hs256("2024-10", &secret)?])
+//!     .with_issuer("https://example.com")
+//!     .with_leeway(Duration::from_secs(30));
+//!
+//! let external_id = passage_flex.auth.verify_nonce(nonce).await?;
+//! let session = issuer.issue(&external_id)?;
+//! // send session.token to the client
+//!
+//! let claims = verifier.verify(&session.token)?;
+//! assert_eq!(claims.sub, external_id);
+//! ```
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::digest::check_hs256_secret;
+use crate::Error;
+
+/// The claims carried by a session token.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    /// The external ID of the user the session belongs to.
+    pub sub: String,
+    /// A unique ID for the session.
+    pub jti: String,
+    /// When the token was issued, as a Unix timestamp.
+    pub iat: i64,
+    /// When the token expires, as a Unix timestamp.
+    pub exp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// Any additional application-defined claims.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A signed session token and the claims it carries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionToken {
+    pub token: String,
+    pub claims: SessionClaims,
+}
+
+/// A key used to sign session tokens.
+#[derive(Clone)]
+pub struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: EncodingKey,
+}
+
+impl SigningKey {
+    /// Creates an HMAC-SHA256 signing key from a shared secret of at least 32 bytes.
+    pub fn hs256(kid: &str, secret: &[u8]) -> Result<Self, Error> {
+        check_hs256_secret(secret)?;
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            key: EncodingKey::from_secret(secret),
+        })
+    }
+
+    /// Creates an Ed25519 signing key from a PKCS#8 PEM-encoded private key.
+    pub fn ed25519_pem(kid: &str, pem: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::EdDSA,
+            key: EncodingKey::from_ed_pem(pem)
+                .map_err(|e| Error::InvalidArgument(format!("invalid Ed25519 key: {}", e)))?,
+        })
+    }
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A key used to verify session tokens.
+#[derive(Clone)]
+pub struct VerificationKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+impl VerificationKey {
+    /// Creates an HMAC-SHA256 verification key from a shared secret of at least 32 bytes.
+    pub fn hs256(kid: &str, secret: &[u8]) -> Result<Self, Error> {
+        check_hs256_secret(secret)?;
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            key: DecodingKey::from_secret(secret),
+        })
+    }
+
+    /// Creates an Ed25519 verification key from a PEM-encoded public key.
+    pub fn ed25519_pem(kid: &str, pem: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::EdDSA,
+            key: DecodingKey::from_ed_pem(pem)
+                .map_err(|e| Error::InvalidArgument(format!("invalid Ed25519 key: {}", e)))?,
+        })
+    }
+}
+
+impl std::fmt::Debug for VerificationKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VerificationKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Issues signed session tokens.
+#[derive(Clone, Debug)]
+pub struct TokenIssuer {
+    key: SigningKey,
+    ttl: Duration,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl TokenIssuer {
+    /// Creates an issuer that signs with `key` and issues tokens valid for `ttl`.
+    pub fn new(key: SigningKey, ttl: Duration) -> Self {
+        Self {
+            key,
+            ttl,
+            issuer: None,
+            audience: None,
+        }
+    }
+
+    /// Sets the `iss` claim of issued tokens.
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    /// Sets the `aud` claim of issued tokens.
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    /// Issues a session token for the user with the given external ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The external ID returned by `Auth::verify_nonce`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `SessionToken` or an `Error`.
+    pub fn issue(&self, external_id: &str) -> Result<SessionToken, Error> {
+        self.issue_with_claims(external_id, serde_json::Map::new())
+    }
+
+    /// Issues a session token carrying additional application-defined claims.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The external ID returned by `Auth::verify_nonce`.
+    /// * `extra` - Additional claims. Registered claim names such as `sub` or `exp` are rejected.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `SessionToken` or an `Error`.
+    pub fn issue_with_claims(
+        &self,
+        external_id: &str,
+        extra: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<SessionToken, Error> {
+        if external_id.is_empty() {
+            return Err(Error::InvalidArgument(
+                "external_id is required".to_string(),
+            ));
+        }
+
+        if let Some(name) = extra
+            .keys()
+            .find(|name| ["sub", "jti", "iat", "exp", "iss", "aud"].contains(&name.as_str()))
+        {
+            return Err(Error::InvalidArgument(format!(
+                "{} is a reserved claim",
+                name
+            )));
+        }
+
+        let iat = Utc::now().timestamp();
+        let claims = SessionClaims {
+            sub: external_id.to_string(),
+            jti: uuid::Uuid::new_v4().to_string(),
+            iat,
+            exp: iat.saturating_add(self.ttl.as_secs() as i64),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            extra,
+        };
+
+        let mut header = Header::new(self.key.algorithm);
+        header.kid = Some(self.key.kid.clone());
+        let token = jsonwebtoken::encode(&header, &claims, &self.key.key)
+            .map_err(|e| Error::Other(format!("failed to sign session token: {}", e)))?;
+
+        Ok(SessionToken { token, claims })
+    }
+}
+
+/// Verifies session tokens against a set of keys.
+#[derive(Clone, Debug)]
+pub struct TokenVerifier {
+    keys: HashMap<String, VerificationKey>,
+    leeway: Duration,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl TokenVerifier {
+    /// Creates a verifier that accepts tokens signed by any of `keys`.
+    pub fn new(keys: Vec<VerificationKey>) -> Self {
+        Self {
+            keys: keys.into_iter().map(|key| (key.kid.clone(), key)).collect(),
+            leeway: Duration::from_secs(60),
+            issuer: None,
+            audience: None,
+        }
+    }
+
+    /// Sets the clock skew tolerated when checking expiry. Defaults to 60 seconds.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Requires tokens to carry this `iss` claim.
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    /// Requires tokens to carry this `aud` claim.
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    /// Adds a key, replacing any existing key with the same ID.
+    pub fn add_key(&mut self, key: VerificationKey) {
+        self.keys.insert(key.kid.clone(), key);
+    }
+
+    /// Removes the key with the given ID, so tokens signed by it are no longer accepted.
+    pub fn remove_key(&mut self, kid: &str) -> bool {
+        self.keys.remove(kid).is_some()
+    }
+
+    /// Verifies a session token's signature, expiry, issuer and audience.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The session token to verify.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the token's `SessionClaims` or `Error::InvalidSessionToken`.
+    pub fn verify(&self, token: &str) -> Result<SessionClaims, Error> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| Error::InvalidSessionToken(e.to_string()))?;
+        let kid = header
+            .kid
+            .ok_or_else(|| Error::InvalidSessionToken("missing key ID".to_string()))?;
+        let key = self
+            .keys
+            .get(&kid)
+            .ok_or_else(|| Error::InvalidSessionToken(format!("unknown key ID {}", kid)))?;
+        if key.algorithm != header.alg {
+            return Err(Error::InvalidSessionToken(
+                "algorithm does not match key".to_string(),
+            ));
+        }
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.leeway = self.leeway.as_secs();
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        jsonwebtoken::decode::<SessionClaims>(token, &key.key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| Error::InvalidSessionToken(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn issuer(kid: &str) -> TokenIssuer {
+        TokenIssuer::new(
+            SigningKey::hs256(kid, SECRET).unwrap(),
+            Duration::from_secs(3600),
+        )
+    }
+
+    fn verifier(kid: &str) -> TokenVerifier {
+        TokenVerifier::new(vec![VerificationKey::hs256(kid, SECRET).unwrap()])
+    }
+
+    #[test]
+    fn short_secrets_are_rejected() {
+        assert!(matches!(
+            SigningKey::hs256("k1", &SECRET[..31]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            VerificationKey::hs256("k1", b""),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn issued_tokens_verify() {
+        let mut extra = serde_json::Map::new();
+        extra.insert("role".to_string(), serde_json::json!("admin"));
+        let session = issuer("k1").issue_with_claims("user-1", extra).unwrap();
+
+        let claims = verifier("k1").verify(&session.token).unwrap();
+        assert_eq!(claims, session.claims);
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.exp - claims.iat, 3600);
+        assert_eq!(claims.extra["role"], "admin");
+    }
+
+    #[test]
+    fn reserved_claims_and_empty_subjects_are_rejected() {
+        let mut extra = serde_json::Map::new();
+        extra.insert("exp".to_string(), serde_json::json!(0));
+        assert!(matches!(
+            issuer("k1").issue_with_claims("user-1", extra),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            issuer("k1").issue(""),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn tokens_from_unknown_or_removed_keys_are_rejected() {
+        let session = issuer("k1").issue("user-1").unwrap();
+        assert!(matches!(
+            verifier("k2").verify(&session.token),
+            Err(Error::InvalidSessionToken(_))
+        ));
+
+        let mut verifier = verifier("k2");
+        verifier.add_key(VerificationKey::hs256("k1", SECRET).unwrap());
+        assert!(verifier.verify(&session.token).is_ok());
+        assert!(verifier.remove_key("k1"));
+        assert!(verifier.verify(&session.token).is_err());
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let session = TokenIssuer::new(
+            SigningKey::hs256("k1", &[7; 32]).unwrap(),
+            Duration::from_secs(3600),
+        )
+        .issue("user-1")
+        .unwrap();
+        assert!(matches!(
+            verifier("k1").verify(&session.token),
+            Err(Error::InvalidSessionToken(_))
+        ));
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected_after_the_leeway() {
+        let session = TokenIssuer::new(SigningKey::hs256("k1", SECRET).unwrap(), Duration::ZERO)
+            .issue("user-1")
+            .unwrap();
+        let claims = SessionClaims {
+            exp: session.claims.iat - 120,
+            ..session.claims
+        };
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("k1".to_string());
+        let token =
+            jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap();
+
+        assert!(matches!(
+            verifier("k1").verify(&token),
+            Err(Error::InvalidSessionToken(_))
+        ));
+        assert!(verifier("k1")
+            .with_leeway(Duration::from_secs(300))
+            .verify(&token)
+            .is_ok());
+    }
+
+    #[test]
+    fn issuer_and_audience_must_match() {
+        let session = issuer("k1")
+            .with_issuer("https://example.com")
+            .with_audience("app")
+            .issue("user-1")
+            .unwrap();
+
+        assert!(verifier("k1")
+            .with_issuer("https://example.com")
+            .with_audience("app")
+            .verify(&session.token)
+            .is_ok());
+        assert!(verifier("k1")
+            .with_issuer("https://other.example.com")
+            .verify(&session.token)
+            .is_err());
+        assert!(verifier("k1")
+            .with_audience("other")
+            .verify(&session.token)
+            .is_err());
+    }
+}
@@ -0,0 +1,388 @@
+//! Server-side sessions that can be revoked.
+//!
+//! Stateless session tokens stay valid until they expire, even after the passkey that created
+//! them is revoked or the user is deactivated. When a `SessionStore` is configured,
+//! `Auth::start_session` records a session for each verified nonce, and the SDK invalidates a
+//! user's sessions whenever it revokes one of their devices, deactivates them, or deletes them.
+//! Check `Auth::validate_session` on each request to honor those revocations.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::models::VerifyOptions;
+//! use passage_flex::session_store::MemorySessionStore;
+//! use passage_flex::PassageFlex;
+//! use std::time::Duration;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_session_store(MemorySessionStore::new(), Duration::from_secs(24 * 60 * 60));
+//!
+//! let verified = passage_flex
+//!     .auth
+//!     .verify_nonce_detailed(nonce, VerifyOptions::default())
+//!     .await?;
+//! let session = passage_flex.auth.start_session(&verified).await?;
+//! // hand session.id to the client, e.g. in a cookie
+//!
+//! // on later requests
+//! let session = passage_flex.auth.validate_session(&session_id).await?;
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// A session created from a verified nonce.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    /// The external ID of the user the session belongs to.
+    pub external_id: String,
+    /// The passkey device used to start the session, if it could be resolved.
+    pub device_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// When the session was revoked, if it has been.
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    /// Whether the session has neither expired nor been revoked.
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at > Utc::now()
+    }
+}
+
+/// Stores sessions and their revocation state.
+pub trait SessionStore: Send + Sync {
+    /// Records a new session. Stores may purge expired sessions, revoked or not, at the same time.
+    fn create(&self, session: Session) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Returns the session with the given ID, if any, whether or not it is still active.
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Session>, Error>>;
+
+    /// Returns the user's sessions that have neither expired nor been revoked.
+    fn list_active<'a>(
+        &'a self,
+        external_id: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Session>, Error>>;
+
+    /// Revokes a single session, returning `false` if it didn't exist or was already revoked.
+    fn revoke<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, Error>>;
+
+    /// Revokes every active session of the user, returning how many were revoked.
+    fn revoke_user<'a>(&'a self, external_id: &'a str) -> BoxFuture<'a, Result<u64, Error>>;
+}
+
+/// An in-memory `SessionStore`.
+///
+/// Sessions are only tracked within a single process; use a shared store such as
+/// `SqliteSessionStore` when several instances serve the same users.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl MemorySessionStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn revoke_where<F: Fn(&Session) -> bool>(&self, matches: F) -> u64 {
+        let now = Utc::now();
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions
+            .values_mut()
+            .filter(|session| session.revoked_at.is_none() && matches(session))
+            .map(|session| session.revoked_at = Some(now))
+            .count() as u64
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn create(&self, session: Session) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+            sessions.retain(|_, session| session.expires_at > now);
+            sessions.insert(session.id.clone(), session);
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Session>, Error>> {
+        Box::pin(async move {
+            let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+            Ok(sessions.get(id).cloned())
+        })
+    }
+
+    fn list_active<'a>(
+        &'a self,
+        external_id: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Session>, Error>> {
+        Box::pin(async move {
+            let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+            Ok(sessions
+                .values()
+                .filter(|session| session.external_id == external_id && session.is_active())
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn revoke<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move { Ok(self.revoke_where(|session| session.id == id) == 1) })
+    }
+
+    fn revoke_user<'a>(&'a self, external_id: &'a str) -> BoxFuture<'a, Result<u64, Error>> {
+        Box::pin(async move { Ok(self.revoke_where(|session| session.external_id == external_id)) })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteSessionStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::path::Path;
+
+    use chrono::{DateTime, Utc};
+    use futures::future::BoxFuture;
+    use rusqlite::{params, Row};
+
+    use super::{Session, SessionStore};
+    use crate::sqlite::SqliteConnection;
+    use crate::Error;
+
+    /// A `SessionStore` backed by a SQLite database, shared by every process using the same file.
+    pub struct SqliteSessionStore {
+        connection: SqliteConnection,
+    }
+
+    impl SqliteSessionStore {
+        /// Opens (or creates) the database at `path`.
+        pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+            let connection = SqliteConnection::open(
+                path,
+                "CREATE TABLE IF NOT EXISTS passage_sessions (
+                    id TEXT PRIMARY KEY,
+                    external_id TEXT NOT NULL,
+                    device_id TEXT,
+                    created_at INTEGER NOT NULL,
+                    expires_at INTEGER NOT NULL,
+                    revoked_at INTEGER
+                );
+                CREATE INDEX IF NOT EXISTS passage_sessions_external_id
+                    ON passage_sessions (external_id);",
+            )?;
+
+            Ok(Self { connection })
+        }
+    }
+
+    const COLUMNS: &str = "id, external_id, device_id, created_at, expires_at, revoked_at";
+
+    fn timestamp(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(seconds, 0).unwrap_or_default()
+    }
+
+    fn session(row: &Row) -> rusqlite::Result<Session> {
+        Ok(Session {
+            id: row.get(0)?,
+            external_id: row.get(1)?,
+            device_id: row.get(2)?,
+            created_at: timestamp(row.get(3)?),
+            expires_at: timestamp(row.get(4)?),
+            revoked_at: row.get::<_, Option<i64>>(5)?.map(timestamp),
+        })
+    }
+
+    impl SessionStore for SqliteSessionStore {
+        fn create(&self, session: Session) -> BoxFuture<'_, Result<(), Error>> {
+            Box::pin(self.connection.run(move |connection| {
+                connection.execute(
+                    "DELETE FROM passage_sessions WHERE expires_at <= ?1",
+                    params![Utc::now().timestamp()],
+                )?;
+                connection
+                    .execute(
+                        &format!(
+                            "INSERT INTO passage_sessions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                            COLUMNS
+                        ),
+                        params![
+                            session.id,
+                            session.external_id,
+                            session.device_id,
+                            session.created_at.timestamp(),
+                            session.expires_at.timestamp(),
+                            session.revoked_at.map(|revoked_at| revoked_at.timestamp()),
+                        ],
+                    )
+                    .map(|_| ())
+            }))
+        }
+
+        fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Session>, Error>> {
+            let id = id.to_string();
+            Box::pin(self.connection.run(move |connection| {
+                let mut statement = connection.prepare(&format!(
+                    "SELECT {} FROM passage_sessions WHERE id = ?1",
+                    COLUMNS
+                ))?;
+                let mut sessions = statement.query_map(params![id], session)?;
+                sessions.next().transpose()
+            }))
+        }
+
+        fn list_active<'a>(
+            &'a self,
+            external_id: &'a str,
+        ) -> BoxFuture<'a, Result<Vec<Session>, Error>> {
+            let external_id = external_id.to_string();
+            Box::pin(self.connection.run(move |connection| {
+                let mut statement = connection.prepare(&format!(
+                    "SELECT {} FROM passage_sessions
+                     WHERE external_id = ?1 AND revoked_at IS NULL AND expires_at > ?2",
+                    COLUMNS
+                ))?;
+                let sessions =
+                    statement.query_map(params![external_id, Utc::now().timestamp()], session)?;
+                sessions.collect()
+            }))
+        }
+
+        fn revoke<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, Error>> {
+            let id = id.to_string();
+            Box::pin(self.connection.run(move |connection| {
+                connection
+                    .execute(
+                        "UPDATE passage_sessions SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+                        params![id, Utc::now().timestamp()],
+                    )
+                    .map(|revoked| revoked == 1)
+            }))
+        }
+
+        fn revoke_user<'a>(&'a self, external_id: &'a str) -> BoxFuture<'a, Result<u64, Error>> {
+            let external_id = external_id.to_string();
+            Box::pin(self.connection.run(move |connection| {
+                connection
+                    .execute(
+                        "UPDATE passage_sessions SET revoked_at = ?2
+                         WHERE external_id = ?1 AND revoked_at IS NULL",
+                        params![external_id, Utc::now().timestamp()],
+                    )
+                    .map(|revoked| revoked as u64)
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(id: &str, external_id: &str, device_id: Option<&str>, ttl_secs: i64) -> Session {
+        let created_at = Utc::now();
+        Session {
+            id: id.to_string(),
+            external_id: external_id.to_string(),
+            device_id: device_id.map(str::to_string),
+            created_at,
+            expires_at: created_at + chrono::Duration::seconds(ttl_secs),
+            revoked_at: None,
+        }
+    }
+
+    async fn revokes_sessions(store: &dyn SessionStore) {
+        store
+            .create(session("phone", "user-1", Some("phone"), 60))
+            .await
+            .unwrap();
+        store
+            .create(session("laptop", "user-1", Some("laptop"), 60))
+            .await
+            .unwrap();
+        store
+            .create(session("unknown", "user-1", None, 60))
+            .await
+            .unwrap();
+        store
+            .create(session("other", "user-2", Some("phone"), 60))
+            .await
+            .unwrap();
+
+        assert!(store.revoke("phone").await.unwrap());
+        assert!(!store.revoke("phone").await.unwrap());
+        let mut active: Vec<String> = store
+            .list_active("user-1")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|session| session.id)
+            .collect();
+        active.sort();
+        assert_eq!(active, ["laptop", "unknown"]);
+
+        assert_eq!(store.revoke_user("user-1").await.unwrap(), 2);
+        assert!(store.list_active("user-1").await.unwrap().is_empty());
+        assert!(store
+            .get("laptop")
+            .await
+            .unwrap()
+            .unwrap()
+            .revoked_at
+            .is_some());
+        assert!(store.get("other").await.unwrap().unwrap().is_active());
+        assert!(!store.revoke("laptop").await.unwrap());
+        assert!(store.revoke("other").await.unwrap());
+    }
+
+    async fn purges_expired_sessions(store: &dyn SessionStore) {
+        store
+            .create(session("expired", "user-1", None, -60))
+            .await
+            .unwrap();
+        assert!(store.get("expired").await.unwrap().is_some());
+        assert!(store.list_active("user-1").await.unwrap().is_empty());
+
+        store
+            .create(session("new", "user-1", None, 60))
+            .await
+            .unwrap();
+        assert_eq!(store.get("expired").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn memory_store_revokes_sessions() {
+        revokes_sessions(&MemorySessionStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn memory_store_purges_expired_sessions() {
+        purges_expired_sessions(&MemorySessionStore::new()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_store_revokes_sessions() {
+        revokes_sessions(&SqliteSessionStore::open(":memory:").unwrap()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_store_purges_expired_sessions() {
+        purges_expired_sessions(&SqliteSessionStore::open(":memory:").unwrap()).await;
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::Connection;
+
+use crate::Error;
+
+/// A SQLite connection shared by the SQLite-backed stores, with queries run off the async
+/// runtime's worker threads.
+#[derive(Clone)]
+pub(crate) struct SqliteConnection {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteConnection {
+    /// Opens (or creates) the database at `path` and applies the store's schema.
+    pub(crate) fn open<P: AsRef<Path>>(path: P, schema: &str) -> Result<Self, Error> {
+        let connection = Connection::open(path).map_err(|e| Error::Store(e.to_string()))?;
+        connection
+            .execute_batch(schema)
+            .map_err(|e| Error::Store(e.to_string()))?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    pub(crate) async fn run<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = connection.lock().unwrap_or_else(|e| e.into_inner());
+            f(&connection)
+        })
+        .await
+        .map_err(|e| Error::Store(e.to_string()))?
+        .map_err(|e| Error::Store(e.to_string()))
+    }
+}
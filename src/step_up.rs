@@ -0,0 +1,411 @@
+//! Step-up authentication for sensitive operations.
+//!
+//! A user's session shows that they authenticated at some point, not that they are at the
+//! keyboard now. Before deleting an account or revoking a passkey, `Auth::begin_step_up` issues
+//! an authenticate transaction bound to the pending action, `Auth::complete_step_up` verifies
+//! the resulting nonce and returns a short-lived `StepUpProof`, and the action handler calls
+//! `Auth::verify_step_up` before doing anything. Each action can have its own freshness window.
+//!
+//! Challenges and proofs are tokens signed with HMAC-SHA256, so they can round-trip through the
+//! client. When a transaction store is configured, the challenge's transaction is also tracked
+//! under a random challenge ID that only the signed challenge carries, so each challenge
+//! completes at most once. Passage doesn't report which transaction a nonce completed, though,
+//! so any nonce for the same user can complete a pending challenge. Without a transaction
+//! store, a challenge can be completed any number of times until it expires.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::step_up::StepUpPolicy;
+//! use passage_flex::PassageFlex;
+//! use std::time::Duration;
+//!
+//! let secret = std::env::var("STEP_UP_SECRET").unwrap().into_bytes();
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_step_up(
+//!     &secret,
+//!     StepUpPolicy::new(Duration::from_secs(5 * 60))
+//!         .with_action("delete_account", Duration::from_secs(60)),
+//! )?;
+//!
+//! let challenge = passage_flex
+//!     .auth
+//!     .begin_step_up(external_id.clone(), "delete_account")
+//!     .await?;
+//! // send challenge.transaction.to_frontend() and challenge.token to the client
+//!
+//! let proof = passage_flex
+//!     .auth
+//!     .complete_step_up(nonce, &challenge.token)
+//!     .await?;
+//!
+//! // in the delete_account handler
+//! passage_flex
+//!     .auth
+//!     .verify_step_up(&proof.token, &external_id, "delete_account")?;
+//! ```
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::digest::check_hs256_secret;
+use crate::models::Transaction;
+use crate::Error;
+
+/// How recently a user must have completed a step-up for each action.
+#[derive(Clone, Debug)]
+pub struct StepUpPolicy {
+    default_window: Duration,
+    windows: HashMap<String, Duration>,
+    challenge_ttl: Duration,
+}
+
+impl StepUpPolicy {
+    /// Creates a policy where a step-up proof stays valid for `default_window` after the
+    /// ceremony, unless an action overrides it.
+    pub fn new(default_window: Duration) -> Self {
+        Self {
+            default_window,
+            windows: HashMap::new(),
+            challenge_ttl: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// Sets the freshness window for one action.
+    pub fn with_action(mut self, action: &str, window: Duration) -> Self {
+        self.windows.insert(action.to_string(), window);
+        self
+    }
+
+    /// Sets how long the user has to complete the ceremony after `Auth::begin_step_up`.
+    /// Defaults to 5 minutes.
+    pub fn with_challenge_ttl(mut self, ttl: Duration) -> Self {
+        self.challenge_ttl = ttl;
+        self
+    }
+
+    /// The freshness window for an action.
+    pub fn window(&self, action: &str) -> Duration {
+        self.windows
+            .get(action)
+            .copied()
+            .unwrap_or(self.default_window)
+    }
+}
+
+/// An authenticate transaction issued for a pending action.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepUpChallenge {
+    pub transaction: Transaction,
+    pub action: String,
+    /// The signed challenge to pass back to `Auth::complete_step_up` with the nonce.
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Proof that a user recently completed a passkey ceremony for an action.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepUpProof {
+    pub external_id: String,
+    pub action: String,
+    pub verified_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// The signed proof to pass to `Auth::verify_step_up`.
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ChallengeClaims {
+    pub(crate) transaction_id: String,
+    pub(crate) challenge_id: String,
+    pub(crate) external_id: String,
+    pub(crate) action: String,
+    pub(crate) expires_at: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProofClaims {
+    external_id: String,
+    action: String,
+    verified_at: i64,
+    expires_at: i64,
+}
+
+/// Signs and checks step-up challenges and proofs.
+pub(crate) struct StepUp {
+    key: Vec<u8>,
+    pub(crate) policy: StepUpPolicy,
+}
+
+impl StepUp {
+    pub(crate) fn new(secret: &[u8], policy: StepUpPolicy) -> Result<Self, Error> {
+        check_hs256_secret(secret)?;
+        Ok(Self {
+            key: secret.to_vec(),
+            policy,
+        })
+    }
+
+    pub(crate) fn challenge(
+        &self,
+        transaction: Transaction,
+        challenge_id: &str,
+        external_id: &str,
+        action: &str,
+    ) -> Result<StepUpChallenge, Error> {
+        let expires_at = expires_at(Utc::now(), self.policy.challenge_ttl);
+        let token = self.sign(&ChallengeClaims {
+            transaction_id: transaction.id.clone(),
+            challenge_id: challenge_id.to_string(),
+            external_id: external_id.to_string(),
+            action: action.to_string(),
+            expires_at: expires_at.timestamp(),
+        })?;
+
+        Ok(StepUpChallenge {
+            transaction,
+            action: action.to_string(),
+            token,
+            expires_at,
+        })
+    }
+
+    /// Checks a challenge's signature and expiry.
+    pub(crate) fn open_challenge(&self, token: &str) -> Result<ChallengeClaims, Error> {
+        let claims: ChallengeClaims = self.open(token)?;
+        if claims.expires_at <= Utc::now().timestamp() {
+            return Err(Error::InvalidStepUp("challenge expired".to_string()));
+        }
+        Ok(claims)
+    }
+
+    pub(crate) fn proof(&self, external_id: &str, action: &str) -> Result<StepUpProof, Error> {
+        let verified_at = Utc::now();
+        let expires_at = expires_at(verified_at, self.policy.window(action));
+        let token = self.sign(&ProofClaims {
+            external_id: external_id.to_string(),
+            action: action.to_string(),
+            verified_at: verified_at.timestamp(),
+            expires_at: expires_at.timestamp(),
+        })?;
+
+        Ok(StepUpProof {
+            external_id: external_id.to_string(),
+            action: action.to_string(),
+            verified_at,
+            expires_at,
+            token,
+        })
+    }
+
+    /// Checks a proof's signature, subject, action and freshness.
+    ///
+    /// Freshness is checked against the action's current window, so shortening a window
+    /// applies to proofs that were already issued.
+    pub(crate) fn verify_proof(
+        &self,
+        token: &str,
+        external_id: &str,
+        action: &str,
+    ) -> Result<StepUpProof, Error> {
+        let claims: ProofClaims = self.open(token)?;
+        if claims.external_id != external_id || claims.action != action {
+            return Err(Error::InvalidStepUp(
+                "proof was issued for a different user or action".to_string(),
+            ));
+        }
+
+        let verified_at = timestamp(claims.verified_at);
+        let expires_at =
+            expires_at(verified_at, self.policy.window(action)).min(timestamp(claims.expires_at));
+        if expires_at <= Utc::now() {
+            return Err(Error::InvalidStepUp("proof expired".to_string()));
+        }
+
+        Ok(StepUpProof {
+            external_id: claims.external_id,
+            action: claims.action,
+            verified_at,
+            expires_at,
+            token: token.to_string(),
+        })
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length")
+    }
+
+    fn sign<T: Serialize>(&self, claims: &T) -> Result<String, Error> {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).map_err(Error::Serde)?);
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        Ok(format!("{}.{}", payload, signature))
+    }
+
+    fn open<T: DeserializeOwned>(&self, token: &str) -> Result<T, Error> {
+        let malformed = || Error::InvalidStepUp("malformed token".to_string());
+        let (payload, signature) = token.split_once('.').ok_or_else(malformed)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| malformed())?;
+
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| Error::InvalidStepUp("invalid signature".to_string()))?;
+
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| malformed())?;
+        serde_json::from_slice(&payload).map_err(|_| malformed())
+    }
+}
+
+impl std::fmt::Debug for StepUp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StepUp")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+fn expires_at(from: DateTime<Utc>, window: Duration) -> DateTime<Utc> {
+    from.checked_add_signed(chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+fn timestamp(seconds: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(seconds, 0).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TransactionKind;
+
+    fn step_up(policy: StepUpPolicy) -> StepUp {
+        StepUp::new(b"step-up-secret-step-up-secret-32b", policy).unwrap()
+    }
+
+    #[test]
+    fn short_secrets_are_rejected() {
+        let policy = || StepUpPolicy::new(Duration::from_secs(60));
+        assert!(matches!(
+            StepUp::new(b"", policy()),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            StepUp::new(b"step-up-secret", policy()),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(StepUp::new(&[7; 32], policy()).is_ok());
+    }
+
+    fn transaction() -> Transaction {
+        Transaction::new(
+            "txn".to_string(),
+            TransactionKind::Authenticate,
+            String::new(),
+            Some("user-1".to_string()),
+        )
+    }
+
+    #[test]
+    fn challenges_round_trip() {
+        let step_up = step_up(StepUpPolicy::new(Duration::from_secs(60)));
+        let challenge = step_up
+            .challenge(transaction(), "challenge-1", "user-1", "delete_account")
+            .unwrap();
+
+        let claims = step_up.open_challenge(&challenge.token).unwrap();
+        assert_eq!(claims.transaction_id, "txn");
+        assert_eq!(claims.challenge_id, "challenge-1");
+        assert_eq!(claims.external_id, "user-1");
+        assert_eq!(claims.action, "delete_account");
+    }
+
+    #[test]
+    fn tampered_and_foreign_tokens_are_rejected() {
+        let step_up = step_up(StepUpPolicy::new(Duration::from_secs(60)));
+        let challenge = step_up
+            .challenge(transaction(), "challenge-1", "user-1", "delete_account")
+            .unwrap();
+
+        let (_, signature) = challenge.token.split_once('.').unwrap();
+        let forged = URL_SAFE_NO_PAD.encode(
+            serde_json::to_vec(&serde_json::json!({
+                "transaction_id": "txn",
+                "challenge_id": "challenge-1",
+                "external_id": "user-2",
+                "action": "delete_account",
+                "expires_at": i64::MAX,
+            }))
+            .unwrap(),
+        );
+        assert!(matches!(
+            step_up.open_challenge(&format!("{}.{}", forged, signature)),
+            Err(Error::InvalidStepUp(_))
+        ));
+
+        let other = StepUp::new(
+            b"other-secret-other-secret-other-s",
+            StepUpPolicy::new(Duration::from_secs(60)),
+        )
+        .unwrap();
+        assert!(other.open_challenge(&challenge.token).is_err());
+        assert!(step_up.open_challenge("not-a-token").is_err());
+    }
+
+    #[test]
+    fn expired_challenges_are_rejected() {
+        let step_up =
+            step_up(StepUpPolicy::new(Duration::from_secs(60)).with_challenge_ttl(Duration::ZERO));
+        let challenge = step_up
+            .challenge(transaction(), "challenge-1", "user-1", "delete_account")
+            .unwrap();
+        assert!(matches!(
+            step_up.open_challenge(&challenge.token),
+            Err(Error::InvalidStepUp(_))
+        ));
+    }
+
+    #[test]
+    fn proofs_only_cover_their_user_and_action() {
+        let step_up = step_up(StepUpPolicy::new(Duration::from_secs(60)));
+        let proof = step_up.proof("user-1", "delete_account").unwrap();
+
+        assert!(step_up
+            .verify_proof(&proof.token, "user-1", "delete_account")
+            .is_ok());
+        assert!(step_up
+            .verify_proof(&proof.token, "user-2", "delete_account")
+            .is_err());
+        assert!(step_up
+            .verify_proof(&proof.token, "user-1", "revoke_passkey")
+            .is_err());
+    }
+
+    #[test]
+    fn shortened_windows_apply_to_issued_proofs() {
+        let proof = step_up(StepUpPolicy::new(Duration::from_secs(60)))
+            .proof("user-1", "delete_account")
+            .unwrap();
+
+        let stricter = step_up(
+            StepUpPolicy::new(Duration::from_secs(60))
+                .with_action("delete_account", Duration::ZERO),
+        );
+        assert!(matches!(
+            stricter.verify_proof(&proof.token, "user-1", "delete_account"),
+            Err(Error::InvalidStepUp(_))
+        ));
+    }
+}
@@ -0,0 +1,388 @@
+//! Spans and metrics for SDK operations and the HTTP calls they make.
+//!
+//! With the `tracing` feature enabled, every public `Auth` and `User` method runs in a
+//! `passage_flex.operation` span, and every request to Passage in a `passage_flex.http` span
+//! nested under it. Operation spans record:
+//!
+//! * `operation` - The method, such as `user.get`.
+//! * `external_id` - An HMAC-SHA256 digest of the user's external ID, once known. The key is
+//!   random and kept for the life of the process, so the digest links a user's operations
+//!   within one process without revealing the ID to anyone hashing guesses such as emails.
+//! * `status` - `ok`, or the kind of error returned, such as `user_not_found`.
+//! * `latency_ms` - How long the operation took.
+//!
+//! HTTP spans record the endpoint as `operation`, the `attempt` number, the response `status`
+//! (`ok`, an HTTP status code, or `timeout`, `connect` or `error` when no response arrived) and
+//! `latency_ms`. Nonces, API keys and other arguments are never recorded.
+//!
+//! With the `metrics` feature enabled, the same outcomes are emitted through the `metrics`
+//! facade, along with the state of the circuit breakers and the cache. The metric names are
+//! listed in the crate documentation.
+//!
+//! Without either feature, these helpers compile to plain awaits.
+
+use std::future::Future;
+
+use crate::circuit_breaker::CircuitState;
+use crate::Error;
+
+#[cfg(any(feature = "tracing", feature = "metrics"))]
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+use tracing::{field, Instrument, Span};
+
+#[cfg(feature = "tracing")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "tracing")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "tracing")]
+use sha2::Sha256;
+
+/// The span and metrics of one public SDK operation.
+pub(crate) struct Operation {
+    #[cfg(feature = "metrics")]
+    name: &'static str,
+    #[cfg(feature = "tracing")]
+    span: Span,
+}
+
+impl Operation {
+    /// Opens the span of an operation on behalf of a user, if the user is known up front.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn start(name: &'static str, external_id: Option<&str>) -> Self {
+        #[cfg(feature = "tracing")]
+        let span = {
+            let span = tracing::info_span!(
+                "passage_flex.operation",
+                operation = name,
+                external_id = field::Empty,
+                status = field::Empty,
+                latency_ms = field::Empty,
+            );
+            if let Some(external_id) = external_id {
+                span.record("external_id", pseudonym(external_id));
+            }
+            span
+        };
+
+        Self {
+            #[cfg(feature = "metrics")]
+            name,
+            #[cfg(feature = "tracing")]
+            span,
+        }
+    }
+
+    /// Runs the operation in its span, recording its outcome and latency. Metrics are only
+    /// recorded for the outermost operation, not for operations it calls.
+    pub(crate) async fn run<T>(
+        self,
+        operation: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        {
+            #[cfg(feature = "metrics")]
+            let outermost = OPERATION.try_with(|_| ()).is_err();
+            #[cfg(feature = "metrics")]
+            let operation = async move {
+                if outermost {
+                    OPERATION.scope((), operation).await
+                } else {
+                    operation.await
+                }
+            };
+
+            let started_at = Instant::now();
+            #[cfg(feature = "tracing")]
+            let result = operation.instrument(self.span.clone()).await;
+            #[cfg(not(feature = "tracing"))]
+            let result = operation.await;
+            let latency = started_at.elapsed();
+            let status = status(&result);
+
+            #[cfg(feature = "tracing")]
+            {
+                self.span.record("status", status);
+                self.span.record("latency_ms", latency.as_millis() as u64);
+            }
+            #[cfg(feature = "metrics")]
+            if outermost {
+                let outcome = if result.is_ok() { "ok" } else { "error" };
+                let error = if result.is_ok() { "none" } else { status };
+                metrics::counter!(
+                    "passage_flex_operations_total",
+                    "operation" => self.name,
+                    "outcome" => outcome,
+                    "error" => error,
+                )
+                .increment(1);
+                metrics::histogram!(
+                    "passage_flex_operation_duration_seconds",
+                    "operation" => self.name,
+                    "outcome" => outcome,
+                )
+                .record(latency.as_secs_f64());
+            }
+            result
+        }
+        #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+        operation.await
+    }
+}
+
+#[cfg(feature = "metrics")]
+tokio::task_local! {
+    /// Set while an operation runs, so the operations it calls aren't counted again.
+    static OPERATION: ();
+}
+
+/// Records the user an operation turned out to be for, such as the user a nonce was issued to.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn record_external_id(external_id: &str) {
+    #[cfg(feature = "tracing")]
+    Span::current().record("external_id", pseudonym(external_id));
+}
+
+/// The digest of an external ID recorded in spans, keyed with a random per-process key.
+#[cfg(feature = "tracing")]
+fn pseudonym(external_id: &str) -> String {
+    static KEY: OnceLock<[u8; 32]> = OnceLock::new();
+    let key = KEY.get_or_init(|| {
+        let mut key = [0; 32];
+        key[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+        key[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+        key
+    });
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(external_id.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Sends one attempt of a request to Passage in its own span.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) async fn http<T, E>(
+    endpoint: &'static str,
+    attempt: u32,
+    request: impl Future<Output = Result<T, crate::openapi::apis::Error<E>>>,
+) -> Result<T, crate::openapi::apis::Error<E>> {
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    {
+        use crate::openapi::apis::Error as ApiError;
+
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "passage_flex.http",
+            operation = endpoint,
+            attempt,
+            status = field::Empty,
+            latency_ms = field::Empty,
+        );
+        let started_at = Instant::now();
+        #[cfg(feature = "tracing")]
+        let result = request.instrument(span.clone()).await;
+        #[cfg(not(feature = "tracing"))]
+        let result = request.await;
+        let latency = started_at.elapsed();
+        let status = match &result {
+            Ok(_) => "ok",
+            Err(ApiError::ResponseError(response)) => response.status.as_str(),
+            Err(ApiError::Reqwest(e)) if e.is_timeout() => "timeout",
+            Err(ApiError::Reqwest(e)) if e.is_connect() => "connect",
+            Err(ApiError::ReqwestMiddleware(e)) if e.is_timeout() => "timeout",
+            Err(ApiError::ReqwestMiddleware(e)) if e.is_connect() => "connect",
+            Err(ApiError::ReqwestMiddleware(e)) if e.is_middleware() => "middleware",
+            Err(_) => "error",
+        };
+
+        #[cfg(feature = "tracing")]
+        {
+            span.record("status", status);
+            span.record("latency_ms", latency.as_millis() as u64);
+        }
+        #[cfg(feature = "metrics")]
+        {
+            metrics::counter!(
+                "passage_flex_http_requests_total",
+                "endpoint" => endpoint,
+                "status" => status.to_string(),
+            )
+            .increment(1);
+            metrics::histogram!(
+                "passage_flex_http_request_duration_seconds",
+                "endpoint" => endpoint,
+            )
+            .record(latency.as_secs_f64());
+        }
+        result
+    }
+    #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+    request.await
+}
+
+/// Reports a circuit breaker's new state as 0 (closed), 1 (half-open) or 2 (open).
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn circuit_state(breaker: &'static str, state: CircuitState) {
+    #[cfg(feature = "metrics")]
+    {
+        let value = match state {
+            CircuitState::Closed => 0.0,
+            CircuitState::HalfOpen => 1.0,
+            CircuitState::Open => 2.0,
+        };
+        metrics::gauge!("passage_flex_circuit_breaker_state", "breaker" => breaker).set(value);
+    }
+}
+
+/// Counts a cache lookup as a hit or a miss.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn cache_lookup(hit: bool) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(
+        "passage_flex_cache_lookups_total",
+        "result" => if hit { "hit" } else { "miss" },
+    )
+    .increment(1);
+}
+
+/// Reports how many entries the in-memory cache holds.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn cache_entries(entries: usize) {
+    #[cfg(feature = "metrics")]
+    metrics::gauge!("passage_flex_cache_entries").set(entries as f64);
+}
+
+/// The outcome of an operation, as recorded in its span and metrics.
+#[cfg(any(feature = "tracing", feature = "metrics"))]
+fn status<T>(result: &Result<T, Error>) -> &'static str {
+    let Err(e) = result else {
+        return "ok";
+    };
+    match e {
+        Error::Reqwest(_) => "http",
+        Error::Serde(_) => "invalid_response",
+        Error::Io(_) => "io",
+        Error::InvalidRequest(_) => "invalid_request",
+        Error::InvalidAccessToken => "invalid_access_token",
+        Error::InvalidNonce => "invalid_nonce",
+        Error::OperationNotAllowed => "operation_not_allowed",
+        Error::DeviceNotFound => "device_not_found",
+        Error::UserNotFound => "user_not_found",
+        Error::UserHasNoPasskeys => "user_has_no_passkeys",
+        Error::InternalServerError => "internal_server_error",
+        Error::Other(_) => "other",
+        Error::InvalidArgument(_) => "invalid_argument",
+        Error::NonceReplayed => "nonce_replayed",
+        Error::Store(_) => "store",
+        Error::TransactionNotFound => "transaction_not_found",
+        Error::TransactionExpired => "transaction_expired",
+        Error::TransactionMismatch => "transaction_mismatch",
+        Error::InvalidSessionToken(_) => "invalid_session_token",
+        Error::SessionNotFound => "session_not_found",
+        Error::SessionExpired => "session_expired",
+        Error::SessionRevoked => "session_revoked",
+        Error::InvalidStepUp(_) => "invalid_step_up",
+        Error::TemporarilyLocked { .. } => "temporarily_locked",
+        Error::RateLimited { .. } => "rate_limited",
+        Error::TooManyRequests => "too_many_requests",
+        Error::CircuitOpen => "circuit_open",
+        Error::Middleware(_) => "middleware",
+        Error::DeadlineExceeded => "deadline_exceeded",
+        Error::Transient(_) => "transient",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    use super::*;
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn pseudonyms_are_stable_but_not_plain_digests() {
+        let pseudonym = pseudonym("user@example.com");
+        assert_eq!(pseudonym, super::pseudonym("user@example.com"));
+        assert_ne!(pseudonym, super::pseudonym("other@example.com"));
+        assert_ne!(pseudonym, crate::digest::sha256_hex("user@example.com"));
+        assert_eq!(pseudonym.len(), 64);
+    }
+
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    #[test]
+    fn statuses_name_the_error() {
+        assert_eq!(status(&Ok::<(), Error>(())), "ok");
+        assert_eq!(status::<()>(&Err(Error::UserNotFound)), "user_not_found");
+        assert_eq!(
+            status::<()>(&Err(Error::TemporarilyLocked {
+                retry_after: std::time::Duration::ZERO
+            })),
+            "temporarily_locked"
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn only_the_outermost_operation_is_counted() {
+        use std::sync::{Arc, Mutex};
+
+        use metrics::{Counter, CounterFn, Gauge, Histogram, Key, KeyName, Metadata, SharedString};
+
+        /// Records the `operation` label of every operation counter increment.
+        #[derive(Default)]
+        struct Operations(Arc<Mutex<Vec<String>>>);
+
+        struct Increment(Arc<Mutex<Vec<String>>>, String);
+
+        impl CounterFn for Increment {
+            fn increment(&self, _: u64) {
+                self.0.lock().unwrap().push(self.1.clone());
+            }
+
+            fn absolute(&self, _: u64) {}
+        }
+
+        impl metrics::Recorder for Operations {
+            fn describe_counter(&self, _: KeyName, _: Option<metrics::Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<metrics::Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<metrics::Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                if key.name() != "passage_flex_operations_total" {
+                    return Counter::noop();
+                }
+                let operation = key
+                    .labels()
+                    .find(|label| label.key() == "operation")
+                    .map(|label| label.value().to_string())
+                    .unwrap_or_default();
+                Counter::from_arc(Arc::new(Increment(self.0.clone(), operation)))
+            }
+
+            fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::noop()
+            }
+
+            fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::noop()
+            }
+        }
+
+        let recorder = Operations::default();
+        metrics::with_local_recorder(&recorder, || {
+            futures::executor::block_on(
+                Operation::start("outer", None)
+                    .run(async { Operation::start("inner", None).run(async { Ok(()) }).await }),
+            )
+            .unwrap();
+            futures::executor::block_on(Operation::start("inner", None).run(async { Ok(()) }))
+                .unwrap();
+        });
+        assert_eq!(*recorder.0.lock().unwrap(), ["outer", "inner"]);
+    }
+}
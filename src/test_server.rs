@@ -0,0 +1,270 @@
+//! A minimal HTTP server that stands in for Passage in unit tests.
+
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::openapi::apis::configuration::Configuration;
+use crate::openapi::models::UserInfo;
+
+/// A status that makes the server close the connection without responding, as if the request
+/// had taken effect but its response was lost.
+pub(crate) const DROP: u16 = 0;
+
+/// A request received by the server.
+#[derive(Clone, Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub(crate) struct TestServer {
+    url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl TestServer {
+    /// Starts a server that answers every request with the status and JSON body `handler`
+    /// returns.
+    pub async fn start<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = (u16, String)> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let _ = serve(stream, &*handler, &recorded).await;
+                });
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    /// A configuration that sends every request to this server.
+    pub fn configuration(&self) -> Configuration {
+        Configuration {
+            base_path: self.url.clone(),
+            ..Configuration::default()
+        }
+    }
+
+    /// The requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+async fn serve<F, Fut>(
+    mut stream: TcpStream,
+    handler: &F,
+    recorded: &Mutex<Vec<Request>>,
+) -> std::io::Result<()>
+where
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = (u16, String)>,
+{
+    let mut buffer = Vec::new();
+    let head_end = loop {
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut body = buffer[head_end + 4..].to_vec();
+    while body.len() < length {
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    let request = Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    };
+    recorded
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(request.clone());
+
+    let (status, body) = handler(request).await;
+    if status == DROP {
+        return Ok(());
+    }
+    let response = format!(
+        "HTTP/1.1 {status} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// A user as Passage returns it, with the given external ID and passkey devices.
+pub(crate) fn user_info(external_id: &str, devices: serde_json::Value) -> UserInfo {
+    serde_json::from_value(user_json(external_id, devices)).unwrap()
+}
+
+/// The JSON body of a user response.
+pub(crate) fn user_response(external_id: &str, devices: serde_json::Value) -> String {
+    serde_json::json!({ "user": user_json(external_id, devices) }).to_string()
+}
+
+/// The JSON body of a user response that includes recent events.
+pub(crate) fn user_response_with_events(
+    external_id: &str,
+    devices: serde_json::Value,
+    events: serde_json::Value,
+) -> String {
+    let mut user = user_json(external_id, devices);
+    user["recent_events"] = events;
+    serde_json::json!({ "user": user }).to_string()
+}
+
+/// A recent event as Passage returns it.
+pub(crate) fn recent_event(action: &str, status: &str, created_at: &str) -> serde_json::Value {
+    serde_json::json!({
+        "created_at": created_at,
+        "completed_at": (status == "complete").then_some(created_at),
+        "id": format!("event-{created_at}"),
+        "ip_addr": "127.0.0.1",
+        "status": status,
+        "type": "",
+        "user_agent": "",
+        "user_agent_display": "",
+        "action": action,
+        "social_login_type": null,
+    })
+}
+
+fn user_json(external_id: &str, devices: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "created_at": "2024-01-01T00:00:00Z",
+        "email": "",
+        "email_verified": false,
+        "external_id": external_id,
+        "id": format!("id-{external_id}"),
+        "last_login_at": "2024-01-01T00:00:00Z",
+        "login_count": 0,
+        "phone": "",
+        "phone_verified": false,
+        "recent_events": [],
+        "social_connections": {},
+        "status": "active",
+        "updated_at": "2024-01-01T00:00:00Z",
+        "user_metadata": null,
+        "webauthn": devices.as_array().is_some_and(|d| !d.is_empty()),
+        "webauthn_devices": devices,
+        "webauthn_types": [],
+    })
+}
+
+/// A passkey device as Passage returns it.
+pub(crate) fn device(id: &str, usage_count: i32, last_login_at: &str) -> serde_json::Value {
+    device_created_at(id, "2024-01-01T00:00:00Z", usage_count, last_login_at)
+}
+
+/// A passkey device created at the given time.
+pub(crate) fn device_created_at(
+    id: &str,
+    created_at: &str,
+    usage_count: i32,
+    last_login_at: &str,
+) -> serde_json::Value {
+    serde_json::json!({
+        "created_at": created_at,
+        "cred_id": format!("cred-{id}"),
+        "friendly_name": id,
+        "id": id,
+        "last_login_at": last_login_at,
+        "type": "passkey",
+        "updated_at": "2024-01-01T00:00:00Z",
+        "usage_count": usage_count,
+        "icons": { "light": null, "dark": null },
+    })
+}
+
+/// The JSON body of a page of users.
+pub(crate) fn users_page(external_ids: &[&str], total_users: i64) -> String {
+    let users: Vec<serde_json::Value> = external_ids
+        .iter()
+        .map(|external_id| {
+            let mut user = user_json(external_id, serde_json::json!([]));
+            let fields = user.as_object_mut().unwrap();
+            for field in [
+                "recent_events",
+                "social_connections",
+                "webauthn",
+                "webauthn_devices",
+                "webauthn_types",
+            ] {
+                fields.remove(field);
+            }
+            user
+        })
+        .collect();
+    let link = serde_json::json!({ "href": "" });
+    serde_json::json!({
+        "users": users,
+        "created_before": 1_700_000_000,
+        "limit": external_ids.len(),
+        "page": 1,
+        "total_users": total_users,
+        "_links": {
+            "first": link, "last": link, "next": link, "previous": link, "self": link,
+        },
+    })
+    .to_string()
+}
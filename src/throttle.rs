@@ -0,0 +1,273 @@
+//! Lockout after repeated failed verifications.
+//!
+//! Passage answers every verification and transaction request it receives, so nothing stops a
+//! client from guessing nonces. When a `ThrottlePolicy` is configured, `Auth` counts failed
+//! nonce verifications per external ID and, via `Auth::for_caller`, per caller key such as a
+//! client IP address. Once a key reaches the failure limit it is locked out, with the lockout
+//! doubling each time it recurs, and verifications and transaction creations for it fail with
+//! `Error::TemporarilyLocked` without calling Passage.
+//!
+//! Only invalid, replayed or mismatched nonces count as failures. An unknown user or one
+//! without passkeys doesn't, so a sign-up can't lock anyone out and lockouts don't reveal
+//! which users exist.
+//!
+//! A successful verification clears the user's failures. Caller failures are never cleared by
+//! a success, so a caller can't reset its count by interleaving requests for a valid user; they
+//! expire with the failure window instead.
+//!
+//! Failures are counted in memory, per process. External IDs and caller keys are kept as
+//! SHA-256 digests, never in plain text.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::throttle::ThrottlePolicy;
+//! use passage_flex::{Error, PassageFlex};
+//! use std::time::Duration;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_throttle(
+//!     ThrottlePolicy::new(5, Duration::from_secs(15 * 60))
+//!         .with_lockout(Duration::from_secs(30), Duration::from_secs(60 * 60)),
+//! );
+//!
+//! match passage_flex.auth.for_caller(&client_ip).verify_nonce(nonce).await {
+//!     Ok(external_id) => {
+//!         // the user is authenticated
+//!     }
+//!     Err(Error::TemporarilyLocked { retry_after }) => {
+//!         // respond with 429 and a Retry-After header
+//!     }
+//!     Err(err) => {
+//!         // nonce was invalid or unable to be verified
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::digest::sha256_hex;
+use crate::Error;
+
+/// How many failures lock a user or caller out, and for how long.
+#[derive(Clone, Debug)]
+pub struct ThrottlePolicy {
+    max_failures: u32,
+    window: Duration,
+    base_lockout: Duration,
+    max_lockout: Duration,
+}
+
+impl ThrottlePolicy {
+    /// Creates a policy that locks a key out after `max_failures` failures within `window`.
+    /// Lockouts start at 30 seconds and are capped at 15 minutes.
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            window,
+            base_lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(15 * 60),
+        }
+    }
+
+    /// Sets the length of the first lockout and the cap on later ones. Each lockout that
+    /// follows another without an intervening success lasts twice as long as the previous one.
+    pub fn with_lockout(mut self, base: Duration, max: Duration) -> Self {
+        self.base_lockout = base;
+        self.max_lockout = max.max(base);
+        self
+    }
+
+    fn lockout(&self, lockouts: u32) -> Duration {
+        self.base_lockout
+            .checked_mul(1 << lockouts.min(31))
+            .unwrap_or(self.max_lockout)
+            .min(self.max_lockout)
+    }
+}
+
+struct Record {
+    failures: u32,
+    window_started_at: Instant,
+    last_failure_at: Instant,
+    locked_until: Option<Instant>,
+    lockouts: u32,
+}
+
+/// Counts failures and tracks lockouts per key.
+pub(crate) struct Throttle {
+    policy: ThrottlePolicy,
+    records: Mutex<HashMap<String, Record>>,
+}
+
+impl Throttle {
+    pub(crate) fn new(policy: ThrottlePolicy) -> Self {
+        Self {
+            policy,
+            records: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The keys a request is counted against.
+    pub(crate) fn keys(external_id: Option<&str>, caller: Option<&str>) -> Vec<String> {
+        external_id
+            .map(user_key)
+            .into_iter()
+            .chain(caller.map(|caller| format!("caller:{}", sha256_hex(caller))))
+            .collect()
+    }
+
+    /// Fails with `Error::TemporarilyLocked` if any of the keys is locked out.
+    pub(crate) fn check(&self, keys: &[String]) -> Result<(), Error> {
+        let now = Instant::now();
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let retry_after = keys
+            .iter()
+            .filter_map(|key| records.get(key)?.locked_until)
+            .filter_map(|locked_until| locked_until.checked_duration_since(now))
+            .filter(|retry_after| !retry_after.is_zero())
+            .max();
+
+        match retry_after {
+            Some(retry_after) => Err(Error::TemporarilyLocked { retry_after }),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn record_failure(&self, keys: &[String]) {
+        let now = Instant::now();
+        let idle = self.policy.window + self.policy.max_lockout;
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.retain(|_, record| {
+            record.locked_until.is_some_and(|until| until > now)
+                || now.duration_since(record.last_failure_at) < idle
+        });
+
+        for key in keys {
+            let record = records.entry(key.clone()).or_insert(Record {
+                failures: 0,
+                window_started_at: now,
+                last_failure_at: now,
+                locked_until: None,
+                lockouts: 0,
+            });
+
+            if now.duration_since(record.window_started_at) > self.policy.window {
+                record.failures = 0;
+                record.window_started_at = now;
+            }
+            record.failures += 1;
+            record.last_failure_at = now;
+
+            if record.failures >= self.policy.max_failures {
+                record.locked_until = Some(now + self.policy.lockout(record.lockouts));
+                record.lockouts += 1;
+                record.failures = 0;
+                record.window_started_at = now;
+            }
+        }
+    }
+
+    /// Clears the failures of a user after a successful request.
+    pub(crate) fn record_success(&self, external_id: &str) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.remove(&user_key(external_id));
+    }
+
+    /// Whether an error is a failure that counts towards a lockout. Only bad nonces count;
+    /// unknown users and network errors don't.
+    pub(crate) fn counts(error: &Error) -> bool {
+        matches!(
+            error,
+            Error::InvalidNonce | Error::NonceReplayed | Error::TransactionMismatch
+        )
+    }
+}
+
+fn user_key(external_id: &str) -> String {
+    format!("user:{}", sha256_hex(external_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(max_failures: u32) -> Throttle {
+        Throttle::new(
+            ThrottlePolicy::new(max_failures, Duration::from_secs(60))
+                .with_lockout(Duration::from_secs(30), Duration::from_secs(300)),
+        )
+    }
+
+    #[test]
+    fn only_bad_nonces_count() {
+        assert!(Throttle::counts(&Error::InvalidNonce));
+        assert!(Throttle::counts(&Error::NonceReplayed));
+        assert!(Throttle::counts(&Error::TransactionMismatch));
+        assert!(!Throttle::counts(&Error::UserNotFound));
+        assert!(!Throttle::counts(&Error::UserHasNoPasskeys));
+        assert!(!Throttle::counts(&Error::InternalServerError));
+    }
+
+    #[test]
+    fn keys_lock_out_after_max_failures() {
+        let throttle = throttle(3);
+        let keys = Throttle::keys(Some("user-1"), Some("10.0.0.1"));
+
+        throttle.record_failure(&keys);
+        throttle.record_failure(&keys);
+        assert!(throttle.check(&keys).is_ok());
+
+        throttle.record_failure(&keys);
+        match throttle.check(&keys) {
+            Err(Error::TemporarilyLocked { retry_after }) => {
+                assert!(retry_after <= Duration::from_secs(30));
+                assert!(retry_after > Duration::from_secs(25));
+            }
+            other => panic!("expected a lockout, got {:?}", other),
+        }
+        assert!(throttle
+            .check(&Throttle::keys(Some("user-2"), None))
+            .is_ok());
+        assert!(throttle
+            .check(&Throttle::keys(None, Some("10.0.0.1")))
+            .is_err());
+    }
+
+    #[test]
+    fn success_clears_the_user_but_not_the_caller() {
+        let throttle = throttle(1);
+        throttle.record_failure(&Throttle::keys(Some("user-1"), Some("10.0.0.1")));
+
+        throttle.record_success("user-1");
+        assert!(throttle
+            .check(&Throttle::keys(Some("user-1"), None))
+            .is_ok());
+        assert!(throttle
+            .check(&Throttle::keys(None, Some("10.0.0.1")))
+            .is_err());
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_cap() {
+        let policy = ThrottlePolicy::new(1, Duration::from_secs(60))
+            .with_lockout(Duration::from_secs(30), Duration::from_secs(100));
+        assert_eq!(policy.lockout(0), Duration::from_secs(30));
+        assert_eq!(policy.lockout(1), Duration::from_secs(60));
+        assert_eq!(policy.lockout(2), Duration::from_secs(100));
+        assert_eq!(policy.lockout(40), Duration::from_secs(100));
+    }
+
+    #[test]
+    fn keys_are_digests() {
+        let keys = Throttle::keys(Some("user@example.com"), Some("10.0.0.1"));
+        assert_eq!(keys.len(), 2);
+        assert!(keys.iter().all(|key| !key.contains("example.com")));
+        assert!(keys.iter().all(|key| !key.contains("10.0.0.1")));
+    }
+}
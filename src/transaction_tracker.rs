@@ -0,0 +1,192 @@
+//! Tracking of issued transactions and the sessions that requested them.
+//!
+//! Nothing in a nonce identifies the browser session that started the ceremony. When a
+//! `TransactionStore` is configured, `Auth::track_transaction` records each issued
+//! transaction against an opaque session binding (such as your session ID) with an expiry,
+//! and `Auth::verify_tracked_nonce` only accepts a nonce if the transaction ID the frontend
+//! reports exists, hasn't expired, was issued to the same session, and was issued for the
+//! same external ID the nonce verifies to.
+//!
+//! Passage doesn't report which transaction a nonce completed, so the nonce itself isn't tied
+//! to the transaction: any valid nonce for the same user can redeem a pending transaction of
+//! that session, and a usernameless transaction accepts a nonce for any user.
+//!
+//! Session bindings are stored as SHA-256 digests, never in plain text.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use passage_flex::transaction_tracker::MemoryTransactionStore;
+//! use passage_flex::PassageFlex;
+//! use std::time::Duration;
+//!
+//! let passage_flex = PassageFlex::new(
+//!     std::env::var("PASSAGE_APP_ID").unwrap(),
+//!     std::env::var("PASSAGE_API_KEY").unwrap(),
+//! )
+//! .with_transaction_store(MemoryTransactionStore::new(), Duration::from_secs(5 * 60));
+//!
+//! let transaction = passage_flex
+//!     .auth
+//!     .create_authenticate_transaction(external_id)
+//!     .await?;
+//! let transaction = passage_flex
+//!     .auth
+//!     .track_transaction(transaction, &session_id)
+//!     .await?;
+//!
+//! // later, when the frontend returns the nonce along with the transaction ID
+//! let external_id = passage_flex
+//!     .auth
+//!     .verify_tracked_nonce(nonce, &transaction.id, &session_id)
+//!     .await?;
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Transaction;
+use crate::Error;
+
+/// An issued transaction awaiting its nonce.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingTransaction {
+    pub transaction: Transaction,
+    /// The SHA-256 digest of the session binding the transaction was issued to.
+    pub session_digest: String,
+}
+
+/// Stores pending transactions until they are redeemed or expire.
+pub trait TransactionStore: Send + Sync {
+    /// Records a pending transaction, keyed by its transaction ID.
+    fn put(&self, pending: PendingTransaction) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Returns the pending transaction with the given ID, if any.
+    fn get<'a>(
+        &'a self,
+        transaction_id: &'a str,
+    ) -> BoxFuture<'a, Result<Option<PendingTransaction>, Error>>;
+
+    /// Removes the pending transaction with the given ID, returning `false` if it was already gone.
+    fn remove<'a>(&'a self, transaction_id: &'a str) -> BoxFuture<'a, Result<bool, Error>>;
+}
+
+/// An in-memory `TransactionStore`.
+///
+/// Transactions are only tracked within a single process; requests for the same session must
+/// be served by the same instance.
+#[derive(Default)]
+pub struct MemoryTransactionStore {
+    pending: Mutex<HashMap<String, PendingTransaction>>,
+}
+
+impl MemoryTransactionStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TransactionStore for MemoryTransactionStore {
+    fn put(&self, pending: PendingTransaction) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let mut transactions = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            transactions.retain(|_, pending| !pending.transaction.is_expired());
+            transactions.insert(pending.transaction.id.clone(), pending);
+            Ok(())
+        })
+    }
+
+    fn get<'a>(
+        &'a self,
+        transaction_id: &'a str,
+    ) -> BoxFuture<'a, Result<Option<PendingTransaction>, Error>> {
+        Box::pin(async move {
+            let transactions = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            Ok(transactions.get(transaction_id).cloned())
+        })
+    }
+
+    fn remove<'a>(&'a self, transaction_id: &'a str) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move {
+            let mut transactions = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            Ok(transactions.remove(transaction_id).is_some())
+        })
+    }
+}
+
+/// Sets the expiry of a transaction about to be tracked.
+pub(crate) fn with_expiry(mut transaction: Transaction, ttl: std::time::Duration) -> Transaction {
+    let ttl = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+    transaction.expires_at = Some(
+        transaction
+            .created_at
+            .checked_add_signed(ttl)
+            .unwrap_or(chrono::DateTime::<Utc>::MAX_UTC),
+    );
+    transaction
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::models::TransactionKind;
+
+    fn pending(id: &str, ttl: Duration) -> PendingTransaction {
+        let transaction = Transaction::new(
+            id.to_string(),
+            TransactionKind::Authenticate,
+            "app".to_string(),
+            None,
+        );
+        PendingTransaction {
+            transaction: with_expiry(transaction, ttl),
+            session_digest: "session".to_string(),
+        }
+    }
+
+    #[test]
+    fn expiry_is_relative_to_creation() {
+        let pending = pending("txn", Duration::from_secs(300));
+        let transaction = pending.transaction;
+        assert_eq!(
+            transaction.expires_at,
+            Some(transaction.created_at + chrono::Duration::seconds(300))
+        );
+        assert!(!transaction.is_expired());
+    }
+
+    #[tokio::test]
+    async fn memory_store_removes_each_transaction_once() {
+        let store = MemoryTransactionStore::new();
+        store
+            .put(pending("txn", Duration::from_secs(60)))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get("txn").await.unwrap().unwrap().session_digest,
+            "session"
+        );
+        assert!(store.remove("txn").await.unwrap());
+        assert!(!store.remove("txn").await.unwrap());
+        assert!(store.get("txn").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn memory_store_prunes_expired_transactions() {
+        let store = MemoryTransactionStore::new();
+        store.put(pending("old", Duration::ZERO)).await.unwrap();
+        store
+            .put(pending("new", Duration::from_secs(60)))
+            .await
+            .unwrap();
+        assert!(store.get("old").await.unwrap().is_none());
+        assert!(store.get("new").await.unwrap().is_some());
+    }
+}
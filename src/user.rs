@@ -1,43 +1,129 @@
-use crate::models::PassageUser;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::apis;
+use crate::cache::{self, CacheBackend};
+use crate::call_options::{self, CallOptions, Replay, Retry};
+use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+use crate::client_limiter::{self, ClientLimiter, Priority};
+use crate::coalesce::{Coalescing, CoalescingStats};
+use crate::models::{EventFilter, PassageUser, UserEvent};
 use crate::openapi::apis::configuration::Configuration;
 use crate::openapi::apis::{user_devices_api, users_api};
+use crate::openapi::models::{UserEventAction, UserEventStatus};
+use crate::session_store::SessionStore;
+use crate::telemetry::{self, Operation};
 use crate::Error;
 
 pub struct User {
     pub(crate) configuration: Configuration,
+    pub(crate) app_id: String,
+    pub(crate) session_store: Option<Arc<dyn SessionStore>>,
+    pub(crate) client_limiter: Option<Arc<ClientLimiter>>,
+    pub(crate) priority: Priority,
+    pub(crate) circuit_breaker: Option<Arc<CircuitBreaker>>,
+    pub(crate) coalescing: Option<Coalescing>,
+    pub(crate) cache: Option<(Arc<dyn CacheBackend>, Duration)>,
+    pub(crate) retry: Retry,
 }
 
 impl User {
     /// Creates a new instance of the `User` struct.
     pub fn new(configuration: Configuration) -> Self {
-        Self { configuration }
+        Self {
+            configuration,
+            app_id: String::new(),
+            session_store: None,
+            client_limiter: None,
+            priority: Priority::Bulk,
+            circuit_breaker: None,
+            coalescing: None,
+            cache: None,
+            retry: Retry::none(),
+        }
+    }
+
+    /// Send a request to Passage through the circuit breaker and client rate limiter, if configured,
+    /// retrying it according to the retry policy when `replay` allows
+    async fn send<T, E, Fut>(
+        &self,
+        operation: &'static str,
+        replay: Replay,
+        request: impl Fn() -> Fut,
+    ) -> Result<T, Error>
+    where
+        Fut: Future<Output = Result<T, crate::openapi::apis::Error<E>>>,
+        Error: From<crate::openapi::apis::Error<E>>,
+    {
+        let request = &request;
+        // Each attempt is boxed, since the layers wrapped around the request would otherwise
+        // copy it into every caller's future.
+        call_options::retrying(self.retry, replay, |attempt| {
+            Box::pin(async move {
+                let request = telemetry::http(operation, attempt, request());
+                let request =
+                    client_limiter::send(self.client_limiter.as_deref(), self.priority, request);
+                match &self.circuit_breaker {
+                    Some(breaker) => breaker.call(request).await,
+                    None => request.await,
+                }
+            })
+        })
+        .await
+    }
+
+    /// The state of the circuit breaker for user management requests, if one is configured.
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker.as_ref().map(|breaker| breaker.state())
+    }
+
+    /// How many reads were coalesced, if request coalescing is enabled.
+    pub fn coalescing_stats(&self) -> Option<CoalescingStats> {
+        self.coalescing.as_ref().map(Coalescing::stats)
     }
 
     /// Get a user's ID in Passage by their external ID
-    async fn get_id(&self, external_id: String) -> Result<String, Error> {
+    pub(crate) async fn get_id(&self, external_id: String) -> Result<String, Error> {
         if external_id.is_empty() {
             return Err(Error::InvalidArgument(
                 "external_id is required".to_string(),
             ));
         }
 
-        let users = users_api::list_paginated_users(
-            &self.configuration,
-            Some(1),
-            Some(1),
-            None,
-            None,
-            Some(&external_id),
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-        )
-        .await
-        .map(|response| response.users)
-        .map_err(Into::into);
+        match &self.coalescing {
+            Some(coalescing) if !call_options::in_effect() => {
+                coalescing
+                    .id(&external_id, self.fetch_id(&external_id))
+                    .await
+            }
+            _ => self.fetch_id(&external_id).await,
+        }
+    }
+
+    async fn fetch_id(&self, external_id: &str) -> Result<String, Error> {
+        let users = self
+            .send("list_paginated_users", Replay::Safe, || {
+                users_api::list_paginated_users(
+                    &self.configuration,
+                    Some(1),
+                    Some(1),
+                    None,
+                    None,
+                    Some(external_id),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+            })
+            .await
+            .map(|response| response.users);
 
         match users {
             Ok(mut users) => match users.len() {
@@ -52,6 +138,130 @@ impl User {
         }
     }
 
+    /// Get one page of the app's users, optionally anchored to a `created_before` timestamp
+    pub(crate) async fn list_page(
+        &self,
+        page: i32,
+        limit: i32,
+        created_before: Option<i64>,
+    ) -> Result<crate::openapi::models::ListPaginatedUsersResponse, Error> {
+        let created_before = created_before
+            .map(i32::try_from)
+            .transpose()
+            .map_err(|_| Error::InvalidArgument("created_before is out of range".to_string()))?;
+
+        self.send("list_paginated_users", Replay::Safe, || {
+            users_api::list_paginated_users(
+                &self.configuration,
+                Some(page),
+                Some(limit),
+                created_before,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+        })
+        .await
+    }
+
+    /// Get a user's full information by their Passage user ID
+    pub(crate) async fn get_info(
+        &self,
+        user_id: &str,
+    ) -> Result<crate::openapi::models::UserInfo, Error> {
+        let request = async {
+            self.send("get_user", Replay::Safe, || {
+                users_api::get_user(&self.configuration, user_id)
+            })
+            .await
+            .map(|response| *response.user)
+        };
+        match &self.coalescing {
+            Some(coalescing) if !call_options::in_effect() => {
+                coalescing.user(user_id, request).await
+            }
+            _ => request.await,
+        }
+    }
+
+    /// Get a user's passkey devices by their Passage user ID
+    pub(crate) async fn list_devices_by_id(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<crate::openapi::models::WebAuthnDevices>, Error> {
+        let request = async {
+            self.send("list_user_devices", Replay::Safe, || {
+                user_devices_api::list_user_devices(&self.configuration, user_id)
+            })
+            .await
+            .map(|response| response.devices)
+        };
+        match &self.coalescing {
+            Some(coalescing) if !call_options::in_effect() => {
+                coalescing.devices(user_id, request).await
+            }
+            _ => request.await,
+        }
+    }
+
+    /// Delete a user's passkey device by their Passage user ID
+    pub(crate) async fn revoke_device_by_id(
+        &self,
+        user_id: &str,
+        device_id: &str,
+    ) -> Result<(), Error> {
+        self.send("delete_user_devices", Replay::Safe, || {
+            user_devices_api::delete_user_devices(&self.configuration, user_id, device_id)
+        })
+        .await
+    }
+
+    /// Delete a user by their Passage user ID
+    pub(crate) async fn delete_by_id(&self, user_id: &str) -> Result<(), Error> {
+        self.send("delete_user", Replay::Safe, || {
+            apis::delete_user(&self.configuration, user_id)
+        })
+        .await
+    }
+
+    /// Read a value from the cache, or fetch and cache it
+    async fn cached<T: Serialize + DeserializeOwned>(
+        &self,
+        key: String,
+        fetch: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let Some((cache, ttl)) = &self.cache else {
+            return fetch.await;
+        };
+
+        if let Ok(Some(value)) = cache.get(&key).await {
+            if let Ok(value) = serde_json::from_str(&value) {
+                telemetry::cache_lookup(true);
+                return Ok(value);
+            }
+        }
+
+        telemetry::cache_lookup(false);
+        let value = fetch.await?;
+        if let Ok(json) = serde_json::to_string(&value) {
+            let _ = cache.put(&key, json, *ttl).await;
+        }
+        Ok(value)
+    }
+
+    /// Revoke all of a user's sessions
+    pub(crate) async fn revoke_sessions(&self, external_id: &str) -> Result<(), Error> {
+        if let Some(store) = &self.session_store {
+            store.revoke_user(external_id).await?;
+        }
+        Ok(())
+    }
+
     /// Retrieves information about a user by their external ID.
     ///
     /// # Arguments
@@ -77,25 +287,279 @@ impl User {
     /// println!("{:?}", passage_user.id);
     /// ```
     pub async fn get(&self, external_id: String) -> Result<Box<PassageUser>, Error> {
-        let user_id = self.get_id(external_id).await?;
-        users_api::get_user(&self.configuration, &user_id)
-            .await
-            .map(|response| {
-                Box::new(PassageUser {
-                    created_at: response.user.created_at,
-                    external_id: response.user.external_id,
-                    id: response.user.id,
-                    last_login_at: response.user.last_login_at,
-                    login_count: response.user.login_count,
-                    status: response.user.status,
-                    updated_at: response.user.updated_at,
-                    user_metadata: response.user.user_metadata,
-                    webauthn: response.user.webauthn,
-                    webauthn_devices: response.user.webauthn_devices,
-                    webauthn_types: response.user.webauthn_types,
-                })
+        Operation::start("user.get", Some(&external_id))
+            .run(self.get_untraced(external_id))
+            .await
+    }
+
+    /// Like `User::get`, with options for this call.
+    pub async fn get_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<Box<PassageUser>, Error> {
+        call_options.run(self.get(external_id)).await
+    }
+
+    async fn get_untraced(&self, external_id: String) -> Result<Box<PassageUser>, Error> {
+        let key = cache::user_key(&self.app_id, &external_id);
+        self.cached(key, async {
+            let user_id = self.get_id(external_id).await?;
+            self.get_info(&user_id).await.map(PassageUser::from)
+        })
+        .await
+        .map(Box::new)
+    }
+
+    /// Creates a user with the given external ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - A unique, immutable string that represents the user.
+    /// * `user_metadata` - Optional metadata to store on the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the created `PassageUser` struct or an `Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::PassageFlex;
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// );
+    ///
+    /// let passage_user = passage_flex
+    ///     .user
+    ///     .create(
+    ///         "00000000-0000-0000-0000-000000000001".to_string(),
+    ///         Some(serde_json::json!({ "plan": "pro" })),
+    ///     )
+    ///     .await
+    ///     .unwrap();
+    /// println!("{:?}", passage_user.id);
+    /// ```
+    pub async fn create(
+        &self,
+        external_id: String,
+        user_metadata: Option<serde_json::Value>,
+    ) -> Result<Box<PassageUser>, Error> {
+        Operation::start("user.create", Some(&external_id))
+            .run(self.create_untraced(external_id, user_metadata))
+            .await
+    }
+
+    /// Like `User::create`, with options for this call.
+    pub async fn create_with_options(
+        &self,
+        external_id: String,
+        user_metadata: Option<serde_json::Value>,
+        call_options: CallOptions,
+    ) -> Result<Box<PassageUser>, Error> {
+        call_options
+            .run(self.create(external_id, user_metadata))
+            .await
+    }
+
+    async fn create_untraced(
+        &self,
+        external_id: String,
+        user_metadata: Option<serde_json::Value>,
+    ) -> Result<Box<PassageUser>, Error> {
+        if external_id.is_empty() {
+            return Err(Error::InvalidArgument(
+                "external_id is required".to_string(),
+            ));
+        }
+
+        let user = self
+            .send("create_user", Replay::WithIdempotencyKey, || {
+                apis::create_user(
+                    &self.configuration,
+                    apis::CreateUserArgs {
+                        email: None,
+                        external_id: Some(external_id.clone()),
+                        phone: None,
+                        user_metadata: user_metadata.clone(),
+                    },
+                )
             })
-            .map_err(Into::into)
+            .await
+            .map(|response| Box::new(PassageUser::from(*response.user)))?;
+        self.invalidate_cache(&external_id).await?;
+        Ok(user)
+    }
+
+    /// Retrieves a user's recent login and registration events, newest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    /// * `filter` - The criteria events must match to be returned.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a vector of `UserEvent` or an `Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::models::EventFilter;
+    /// use passage_flex::openapi::models::UserEventAction;
+    /// use passage_flex::PassageFlex;
+    /// use chrono::{Duration, Utc};
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// );
+    ///
+    /// let external_id = "00000000-0000-0000-0000-000000000001";
+    /// let logins = passage_flex
+    ///     .user
+    ///     .events(
+    ///         external_id.to_string(),
+    ///         EventFilter {
+    ///             action: Some(UserEventAction::Login),
+    ///             since: Some(Utc::now() - Duration::days(7)),
+    ///             ..Default::default()
+    ///         },
+    ///     )
+    ///     .await
+    ///     .unwrap();
+    /// for event in logins {
+    ///     println!("{} {}", event.created_at, event.ip_addr);
+    /// }
+    /// ```
+    pub async fn events(
+        &self,
+        external_id: String,
+        filter: EventFilter,
+    ) -> Result<Vec<UserEvent>, Error> {
+        Operation::start("user.events", Some(&external_id))
+            .run(self.events_untraced(external_id, filter))
+            .await
+    }
+
+    /// Like `User::events`, with options for this call.
+    pub async fn events_with_options(
+        &self,
+        external_id: String,
+        filter: EventFilter,
+        call_options: CallOptions,
+    ) -> Result<Vec<UserEvent>, Error> {
+        call_options.run(self.events(external_id, filter)).await
+    }
+
+    async fn events_untraced(
+        &self,
+        external_id: String,
+        filter: EventFilter,
+    ) -> Result<Vec<UserEvent>, Error> {
+        let user_id = self.get_id(external_id).await?;
+        let info = self.get_info(&user_id).await?;
+
+        let mut events = info
+            .recent_events
+            .into_iter()
+            .map(UserEvent::try_from)
+            .filter(|event| event.as_ref().map_or(true, |event| filter.matches(event)))
+            .collect::<Result<Vec<_>, _>>()?;
+        events.sort_by_key(|event| std::cmp::Reverse(event.created_at));
+        Ok(events)
+    }
+
+    /// Retrieves a user's most recent completed login event.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `UserEvent`, or `None` if the user has no recent completed
+    /// login, or an `Error`.
+    pub async fn last_successful_login(
+        &self,
+        external_id: String,
+    ) -> Result<Option<UserEvent>, Error> {
+        Operation::start("user.last_successful_login", Some(&external_id))
+            .run(self.last_successful_login_untraced(external_id))
+            .await
+    }
+
+    /// Like `User::last_successful_login`, with options for this call.
+    pub async fn last_successful_login_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<Option<UserEvent>, Error> {
+        call_options
+            .run(self.last_successful_login(external_id))
+            .await
+    }
+
+    async fn last_successful_login_untraced(
+        &self,
+        external_id: String,
+    ) -> Result<Option<UserEvent>, Error> {
+        self.events(
+            external_id,
+            EventFilter {
+                action: Some(UserEventAction::Login),
+                status: Some(UserEventStatus::Complete),
+                ..Default::default()
+            },
+        )
+        .await
+        .map(|events| events.into_iter().next())
+    }
+
+    /// Retrieves a user's recent registration events that were never completed.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a vector of `UserEvent` or an `Error`.
+    pub async fn incomplete_registrations(
+        &self,
+        external_id: String,
+    ) -> Result<Vec<UserEvent>, Error> {
+        Operation::start("user.incomplete_registrations", Some(&external_id))
+            .run(self.incomplete_registrations_untraced(external_id))
+            .await
+    }
+
+    /// Like `User::incomplete_registrations`, with options for this call.
+    pub async fn incomplete_registrations_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<Vec<UserEvent>, Error> {
+        call_options
+            .run(self.incomplete_registrations(external_id))
+            .await
+    }
+
+    async fn incomplete_registrations_untraced(
+        &self,
+        external_id: String,
+    ) -> Result<Vec<UserEvent>, Error> {
+        self.events(
+            external_id,
+            EventFilter {
+                action: Some(UserEventAction::Register),
+                status: Some(UserEventStatus::Incomplete),
+                ..Default::default()
+            },
+        )
+        .await
     }
 
     /// Retrieves information about a user's passkey devices.
@@ -128,15 +592,37 @@ impl User {
         &self,
         external_id: String,
     ) -> Result<Vec<crate::openapi::models::WebAuthnDevices>, Error> {
-        let user_id = self.get_id(external_id).await?;
-        user_devices_api::list_user_devices(&self.configuration, &user_id)
+        Operation::start("user.list_devices", Some(&external_id))
+            .run(self.list_devices_untraced(external_id))
             .await
-            .map(|response| response.devices)
-            .map_err(Into::into)
+    }
+
+    /// Like `User::list_devices`, with options for this call.
+    pub async fn list_devices_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<Vec<crate::openapi::models::WebAuthnDevices>, Error> {
+        call_options.run(self.list_devices(external_id)).await
+    }
+
+    async fn list_devices_untraced(
+        &self,
+        external_id: String,
+    ) -> Result<Vec<crate::openapi::models::WebAuthnDevices>, Error> {
+        let key = cache::devices_key(&self.app_id, &external_id);
+        self.cached(key, async {
+            let user_id = self.get_id(external_id).await?;
+            self.list_devices_by_id(&user_id).await
+        })
+        .await
     }
 
     /// Revokes a user's passkey device.
     ///
+    /// All of the user's sessions are revoked in the configured session store, since the device a
+    /// session was started with is only a best guess.
+    ///
     /// # Arguments
     ///
     /// * `external_id` - The unique, immutable ID that represents the user.
@@ -178,13 +664,399 @@ impl User {
     /// }
     /// ```
     pub async fn revoke_device(&self, external_id: String, device_id: String) -> Result<(), Error> {
+        Operation::start("user.revoke_device", Some(&external_id))
+            .run(self.revoke_device_untraced(external_id, device_id))
+            .await
+    }
+
+    /// Like `User::revoke_device`, with options for this call.
+    pub async fn revoke_device_with_options(
+        &self,
+        external_id: String,
+        device_id: String,
+        call_options: CallOptions,
+    ) -> Result<(), Error> {
+        call_options
+            .run(self.revoke_device(external_id, device_id))
+            .await
+    }
+
+    async fn revoke_device_untraced(
+        &self,
+        external_id: String,
+        device_id: String,
+    ) -> Result<(), Error> {
         if device_id.is_empty() {
             return Err(Error::InvalidArgument("device_id is required".to_string()));
         }
 
-        let user_id = self.get_id(external_id).await?;
-        user_devices_api::delete_user_devices(&self.configuration, &user_id, &device_id)
+        let user_id = self.get_id(external_id.clone()).await?;
+        self.revoke_device_by_id(&user_id, &device_id).await?;
+        self.revoke_sessions(&external_id).await?;
+        self.invalidate_cache(&external_id).await
+    }
+
+    /// Deletes a user.
+    ///
+    /// Any sessions recorded for the user in the configured session store are revoked.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `()` or an `Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::PassageFlex;
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// );
+    ///
+    /// let external_id = "00000000-0000-0000-0000-000000000001";
+    /// if let Err(err) = passage_flex.user.delete(external_id.to_string()).await {
+    ///     // user couldn't be deleted
+    /// }
+    /// ```
+    pub async fn delete(&self, external_id: String) -> Result<(), Error> {
+        Operation::start("user.delete", Some(&external_id))
+            .run(self.delete_untraced(external_id))
+            .await
+    }
+
+    /// Like `User::delete`, with options for this call.
+    pub async fn delete_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<(), Error> {
+        call_options.run(self.delete(external_id)).await
+    }
+
+    async fn delete_untraced(&self, external_id: String) -> Result<(), Error> {
+        let user_id = self.get_id(external_id.clone()).await?;
+        self.delete_by_id(&user_id).await?;
+        self.revoke_sessions(&external_id).await?;
+        self.invalidate_cache(&external_id).await
+    }
+
+    /// Deactivates a user, so they can no longer register or authenticate with passkeys.
+    ///
+    /// Any sessions recorded for the user in the configured session store are revoked.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the updated `PassageUser` or an `Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::PassageFlex;
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// );
+    ///
+    /// let external_id = "00000000-0000-0000-0000-000000000001";
+    /// let passage_user = passage_flex
+    ///     .user
+    ///     .deactivate(external_id.to_string())
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub async fn deactivate(&self, external_id: String) -> Result<Box<PassageUser>, Error> {
+        Operation::start("user.deactivate", Some(&external_id))
+            .run(self.deactivate_untraced(external_id))
             .await
-            .map_err(Into::into)
+    }
+
+    /// Like `User::deactivate`, with options for this call.
+    pub async fn deactivate_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<Box<PassageUser>, Error> {
+        call_options.run(self.deactivate(external_id)).await
+    }
+
+    async fn deactivate_untraced(&self, external_id: String) -> Result<Box<PassageUser>, Error> {
+        let user_id = self.get_id(external_id.clone()).await?;
+        let user = self
+            .send("deactivate_user", Replay::Safe, || {
+                apis::deactivate_user(&self.configuration, &user_id)
+            })
+            .await
+            .map(|response| Box::new(PassageUser::from(*response.user)))?;
+        self.revoke_sessions(&external_id).await?;
+        self.invalidate_cache(&external_id).await?;
+        Ok(user)
+    }
+
+    /// Reactivates a user that was previously deactivated.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the updated `PassageUser` or an `Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use passage_flex::PassageFlex;
+    ///
+    /// let passage_flex = PassageFlex::new(
+    ///     std::env::var("PASSAGE_APP_ID").unwrap(),
+    ///     std::env::var("PASSAGE_API_KEY").unwrap(),
+    /// );
+    ///
+    /// let external_id = "00000000-0000-0000-0000-000000000001";
+    /// let passage_user = passage_flex
+    ///     .user
+    ///     .activate(external_id.to_string())
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub async fn activate(&self, external_id: String) -> Result<Box<PassageUser>, Error> {
+        Operation::start("user.activate", Some(&external_id))
+            .run(self.activate_untraced(external_id))
+            .await
+    }
+
+    /// Like `User::activate`, with options for this call.
+    pub async fn activate_with_options(
+        &self,
+        external_id: String,
+        call_options: CallOptions,
+    ) -> Result<Box<PassageUser>, Error> {
+        call_options.run(self.activate(external_id)).await
+    }
+
+    async fn activate_untraced(&self, external_id: String) -> Result<Box<PassageUser>, Error> {
+        let user_id = self.get_id(external_id.clone()).await?;
+        let user = self
+            .send("activate_user", Replay::Safe, || {
+                apis::activate_user(&self.configuration, &user_id)
+            })
+            .await
+            .map(|response| Box::new(PassageUser::from(*response.user)))?;
+        self.invalidate_cache(&external_id).await?;
+        Ok(user)
+    }
+
+    /// Drops a user's cached reads, such as after changing the user outside of this client.
+    ///
+    /// Changes made through this client invalidate the cache automatically.
+    ///
+    /// # Arguments
+    ///
+    /// * `external_id` - The unique, immutable ID that represents the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `()` or an `Error`.
+    pub async fn invalidate_cache(&self, external_id: &str) -> Result<(), Error> {
+        Operation::start("user.invalidate_cache", Some(external_id))
+            .run(self.invalidate_cache_untraced(external_id))
+            .await
+    }
+
+    /// Like `User::invalidate_cache`, with options for this call.
+    pub async fn invalidate_cache_with_options(
+        &self,
+        external_id: &str,
+        call_options: CallOptions,
+    ) -> Result<(), Error> {
+        call_options.run(self.invalidate_cache(external_id)).await
+    }
+
+    async fn invalidate_cache_untraced(&self, external_id: &str) -> Result<(), Error> {
+        if let Some((cache, _)) = &self.cache {
+            cache
+                .remove(&cache::user_key(&self.app_id, external_id))
+                .await?;
+            cache
+                .remove(&cache::devices_key(&self.app_id, external_id))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryCache;
+    use crate::session_store::{MemorySessionStore, Session};
+    use crate::test_server::{user_response, users_page, TestServer};
+
+    fn session(id: &str, device_id: Option<&str>) -> Session {
+        let created_at = chrono::Utc::now();
+        Session {
+            id: id.to_string(),
+            external_id: "user-1".to_string(),
+            device_id: device_id.map(str::to_string),
+            created_at,
+            expires_at: created_at + chrono::Duration::hours(1),
+            revoked_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn revoke_device_revokes_all_of_the_users_sessions() {
+        let server = TestServer::start(|request| async move {
+            match request.method.as_str() {
+                "GET" => (200, users_page(&["user-1"], 1)),
+                _ => (200, "{}".to_string()),
+            }
+        })
+        .await;
+        let store = Arc::new(MemorySessionStore::new());
+        store.create(session("phone", Some("phone"))).await.unwrap();
+        store
+            .create(session("laptop", Some("laptop")))
+            .await
+            .unwrap();
+        let mut user = User::new(server.configuration());
+        user.session_store = Some(store.clone());
+
+        user.revoke_device("user-1".to_string(), "phone".to_string())
+            .await
+            .unwrap();
+
+        assert!(store.list_active("user-1").await.unwrap().is_empty());
+        let requests = server.requests();
+        assert_eq!(requests[1].method, "DELETE");
+        assert_eq!(requests[1].path, "/users/id-user-1/devices/phone");
+    }
+
+    fn cached_user(server: &TestServer, app_id: &str, cache: &Arc<MemoryCache>) -> User {
+        let mut user = User::new(server.configuration());
+        user.app_id = app_id.to_string();
+        user.cache = Some((cache.clone(), Duration::from_secs(60)));
+        user
+    }
+
+    #[tokio::test]
+    async fn reads_are_cached_per_app_until_invalidated() {
+        let server = TestServer::start(|request| async move {
+            match request.path.as_str() {
+                path if path.starts_with("/users?") => (200, users_page(&["user-1"], 1)),
+                _ => (200, user_response("user-1", serde_json::json!([]))),
+            }
+        })
+        .await;
+        let cache = Arc::new(MemoryCache::new(10));
+        let app_1 = cached_user(&server, "app-1", &cache);
+        let app_2 = cached_user(&server, "app-2", &cache);
+
+        app_1.get("user-1".to_string()).await.unwrap();
+        app_1.get("user-1".to_string()).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+
+        app_2.get("user-1".to_string()).await.unwrap();
+        assert_eq!(server.requests().len(), 4);
+
+        app_1.invalidate_cache("user-1").await.unwrap();
+        app_1.get("user-1".to_string()).await.unwrap();
+        app_2.get("user-1".to_string()).await.unwrap();
+        assert_eq!(server.requests().len(), 6);
+    }
+
+    const SERVER_ERROR: &str = r#"{"code":"internal_server_error","error":"boom"}"#;
+
+    /// A server that fails the first `failures` requests, leaves request number `stall` waiting
+    /// for longer than any test runs, and answers the rest for `user-1`.
+    async fn stalling_server(failures: usize, stall: usize) -> TestServer {
+        let answered = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        TestServer::start(move |request| {
+            let answered = answered.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                if answered < failures {
+                    return (500, SERVER_ERROR.to_string());
+                }
+                if answered == stall {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+                match request.path.as_str() {
+                    path if path.starts_with("/users?") => (200, users_page(&["user-1"], 1)),
+                    _ => (200, user_response("user-1", serde_json::json!([]))),
+                }
+            }
+        })
+        .await
+    }
+
+    fn short_timeout() -> CallOptions {
+        CallOptions::new().with_timeout(Duration::from_millis(100))
+    }
+
+    #[tokio::test]
+    async fn a_dropped_probe_lets_the_breaker_probe_again() {
+        let server = stalling_server(1, 1).await;
+        let mut user = User::new(server.configuration());
+        user.circuit_breaker = Some(Arc::new(CircuitBreaker::new(
+            "test",
+            crate::circuit_breaker::CircuitBreakerPolicy::new(1, Duration::from_millis(10)),
+        )));
+
+        let result = user.get("user-1".to_string()).await;
+        assert!(matches!(result, Err(Error::InternalServerError)));
+        assert_eq!(user.circuit_state(), Some(CircuitState::Open));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let result = user
+            .get_with_options("user-1".to_string(), short_timeout())
+            .await;
+        assert!(matches!(result, Err(Error::DeadlineExceeded)));
+
+        user.get("user-1".to_string()).await.unwrap();
+        assert_eq!(user.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn a_dropped_coalesced_read_is_sent_by_a_waiting_caller() {
+        let server = stalling_server(0, 0).await;
+        let mut user = User::new(server.configuration());
+        user.coalescing = Some(Coalescing::new());
+
+        let mut leader = Box::pin(user.get("user-1".to_string()));
+        let _ = tokio::time::timeout(Duration::from_millis(100), &mut leader).await;
+        assert_eq!(server.requests().len(), 1);
+        let mut waiter = Box::pin(user.get("user-1".to_string()));
+        assert!(futures::poll!(&mut waiter).is_pending());
+        drop(leader);
+
+        assert_eq!(waiter.await.unwrap().external_id, "user-1");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_dropped_read_caches_nothing() {
+        let server = stalling_server(0, 1).await;
+        let cache = Arc::new(MemoryCache::new(10));
+        let user = cached_user(&server, "app", &cache);
+
+        let result = user
+            .get_with_options("user-1".to_string(), short_timeout())
+            .await;
+        assert!(matches!(result, Err(Error::DeadlineExceeded)));
+        let key = cache::user_key("app", "user-1");
+        assert_eq!(cache.get(&key).await.unwrap(), None);
+
+        user.get("user-1".to_string()).await.unwrap();
+        assert!(cache.get(&key).await.unwrap().is_some());
     }
 }